@@ -0,0 +1,50 @@
+//! Measures the cost of each [`Interpolation`] mode when sampling a clip, the way the sfx and music
+//! renderers do. Run with `cargo bench --bench interpolation`.
+
+use sasa::{AudioClip, Frame, Interpolation};
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+const SAMPLE_RATE: u32 = 44100;
+const OUTPUT_RATE: f32 = 48000.;
+const ROUNDS: usize = 20;
+
+fn main() {
+    let frames = (0..SAMPLE_RATE * 10)
+        .map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let s = (t * 440. * std::f32::consts::TAU).sin();
+            Frame(s, -s)
+        })
+        .collect();
+    let clip = AudioClip::from_raw(frames, SAMPLE_RATE);
+    let delta = 1. / OUTPUT_RATE;
+    let samples = (clip.length() * OUTPUT_RATE) as usize;
+
+    for interpolation in [
+        Interpolation::Linear,
+        Interpolation::CubicHermite,
+        Interpolation::Sinc,
+    ] {
+        let clip = clip.with_interpolation(interpolation);
+        let mut best = Duration::MAX;
+        for _ in 0..ROUNDS {
+            let start = Instant::now();
+            let mut sum = Frame::default();
+            for i in 0..samples {
+                if let Some(frame) = clip.sample(black_box(i as f32 * delta)) {
+                    sum = sum + frame;
+                }
+            }
+            black_box(sum);
+            best = best.min(start.elapsed());
+        }
+        println!(
+            "{interpolation:?}: {:.2} ns/sample ({:.3} ms per second of output)",
+            best.as_nanos() as f64 / samples as f64,
+            best.as_secs_f64() * 1000. / clip.length() as f64,
+        );
+    }
+}
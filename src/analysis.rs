@@ -0,0 +1,255 @@
+use crate::{
+    resample::{kernel_at, kernel_table},
+    Frame,
+};
+
+/// Oversampling factor used to find the true peak, as recommended by ITU-R BS.1770 for rates
+/// up to 48 kHz.
+const TRUE_PEAK_OVERSAMPLING: usize = 4;
+/// Zero crossings of the interpolation filter on each side, giving 12 taps per phase like the
+/// reference filter of BS.1770.
+const TRUE_PEAK_ZERO_CROSSINGS: usize = 6;
+
+/// Absolute gate of the integrated loudness, in LUFS.
+const ABSOLUTE_GATE: f64 = -70.;
+/// Relative gate of the integrated loudness, in LU below the loudness of the blocks passing the
+/// absolute gate.
+const RELATIVE_GATE: f64 = -10.;
+
+/// Levels of a clip. Amplitudes are linear, 1 being full scale.
+#[derive(Debug, Clone, Copy)]
+pub struct Loudness {
+    /// Largest absolute sample value.
+    pub peak: f32,
+    /// Largest absolute value of the signal between samples, as a DAC would reconstruct it.
+    /// Never below `peak`.
+    pub true_peak: f32,
+    /// Root mean square of both channels.
+    pub rms: f32,
+    /// Integrated loudness in LUFS following EBU R128. `None` if the clip is shorter than a
+    /// gating block (400 ms) or silent.
+    pub integrated: Option<f32>,
+}
+
+impl Loudness {
+    /// Returns the linear gain that brings the integrated loudness to `target` LUFS.
+    pub fn gain_to(&self, target: f32) -> Option<f32> {
+        gain_to(self.integrated, target)
+    }
+}
+
+/// Returns the linear gain that brings an `integrated` loudness to `target` LUFS.
+pub(crate) fn gain_to(integrated: Option<f32>, target: f32) -> Option<f32> {
+    integrated.map(|loudness| 10f32.powf((target - loudness) / 20.))
+}
+
+/// Biquad filter in transposed direct form II.
+#[derive(Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    #[inline]
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// The two stages of the K-weighting filter of BS.1770, derived for `sample_rate` from the
+/// analog prototypes so that they match the coefficients of the standard at 48 kHz.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let fs = sample_rate as f64;
+
+    // high shelf modelling the acoustic effect of the head
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (std::f64::consts::PI * f0 / fs).tan();
+    let vh = 10f64.powf(gain / 20.);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1. + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2. * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2. * (k * k - 1.) / a0, (1. - k / q + k * k) / a0],
+        z: [0.; 2],
+    };
+
+    // high pass (the RLB weighting)
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (std::f64::consts::PI * f0 / fs).tan();
+    let a0 = 1. + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1., -2., 1.],
+        a: [2. * (k * k - 1.) / a0, (1. - k / q + k * k) / a0],
+        z: [0.; 2],
+    };
+
+    [shelf, high_pass]
+}
+
+fn true_peak(frames: &[Frame]) -> f32 {
+    let table = kernel_table(TRUE_PEAK_ZERO_CROSSINGS, 8.);
+    let taps = TRUE_PEAK_ZERO_CROSSINGS as isize;
+    // coefficients of the phases between two samples, applied to the samples from
+    // `-taps + 1` to `taps` around the earlier one
+    let phases: Vec<Vec<f32>> = (1..TRUE_PEAK_OVERSAMPLING)
+        .map(|phase| {
+            let t = phase as f32 / TRUE_PEAK_OVERSAMPLING as f32;
+            (-taps + 1..=taps)
+                .map(|k| kernel_at(&table, t - k as f32))
+                .collect()
+        })
+        .collect();
+
+    let get = |index: isize| {
+        usize::try_from(index)
+            .ok()
+            .and_then(|it| frames.get(it))
+            .copied()
+            .unwrap_or_default()
+    };
+    let mut peak = 0f32;
+    for n in 0..frames.len() as isize {
+        for coefficients in &phases {
+            let mut sum = Frame::default();
+            for (k, c) in (-taps + 1..=taps).zip(coefficients) {
+                sum = sum + get(n + k) * *c;
+            }
+            peak = peak.max(sum.0.abs()).max(sum.1.abs());
+        }
+    }
+    peak
+}
+
+/// Measures the integrated loudness of `frames` played at `sample_rate`, see
+/// [`Loudness::integrated`].
+pub(crate) fn integrated(frames: &[Frame], sample_rate: u32) -> Option<f32> {
+    // blocks of 400 ms overlapping by 75% are made of four 100 ms steps
+    let step = (sample_rate as usize + 5) / 10;
+    let block = step * 4;
+    if step == 0 || frames.len() < block {
+        return None;
+    }
+
+    let [mut left, mut right] = [k_weighting(sample_rate), k_weighting(sample_rate)];
+    let steps: Vec<f64> = frames
+        .chunks_exact(step)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .map(|frame| {
+                    let l = left.iter_mut().fold(frame.0 as f64, |x, f| f.process(x));
+                    let r = right.iter_mut().fold(frame.1 as f64, |x, f| f.process(x));
+                    l * l + r * r
+                })
+                .sum()
+        })
+        .collect();
+
+    let loudness = |power: f64| -0.691 + 10. * power.log10();
+    let powers: Vec<f64> = steps
+        .windows(4)
+        .map(|it| it.iter().sum::<f64>() / block as f64)
+        .filter(|it| loudness(*it) > ABSOLUTE_GATE)
+        .collect();
+    if powers.is_empty() {
+        return None;
+    }
+    let mean = |powers: &mut dyn Iterator<Item = f64>| {
+        let (sum, count) = powers.fold((0., 0), |(sum, count), it| (sum + it, count + 1));
+        sum / count as f64
+    };
+    let gate = loudness(mean(&mut powers.iter().copied())) + RELATIVE_GATE;
+    let gated = mean(&mut powers.iter().copied().filter(|it| loudness(*it) > gate));
+    Some(loudness(gated) as f32)
+}
+
+/// Measures the levels of `frames`, whose integrated loudness is already known.
+pub(crate) fn loudness(frames: &[Frame], integrated: Option<f32>) -> Loudness {
+    let mut peak = 0f32;
+    let mut square_sum = 0.;
+    for frame in frames {
+        peak = peak.max(frame.0.abs()).max(frame.1.abs());
+        square_sum += (frame.0 * frame.0 + frame.1 * frame.1) as f64;
+    }
+    let rms = if frames.is_empty() {
+        0.
+    } else {
+        (square_sum / (frames.len() * 2) as f64).sqrt() as f32
+    };
+    Loudness {
+        peak,
+        true_peak: true_peak(frames).max(peak),
+        rms,
+        integrated,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    /// A stereo sine of `frequency` Hz peaking at `level` dBFS on both channels.
+    fn sine(frequency: f32, level: f32, seconds: f32) -> Vec<Frame> {
+        let amplitude = 10f32.powf(level / 20.);
+        (0..(seconds * SAMPLE_RATE as f32) as usize)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                let sample = (t * frequency * std::f32::consts::TAU).sin() * amplitude;
+                Frame(sample, sample)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reference_sine() {
+        // EBU Tech 3341, case 1
+        let frames = sine(1000., -23., 20.);
+        let loudness = integrated(&frames, SAMPLE_RATE).unwrap();
+        assert!((loudness + 23.).abs() < 0.1, "{loudness}");
+        // and with a 20 dB step
+        let frames = sine(1000., -33., 20.);
+        let loudness = integrated(&frames, SAMPLE_RATE).unwrap();
+        assert!((loudness + 33.).abs() < 0.1, "{loudness}");
+    }
+
+    #[test]
+    fn levels() {
+        let frames = sine(1000., -6., 1.);
+        let loudness = loudness(&frames, None);
+        let amplitude = 10f32.powf(-6. / 20.);
+        assert!((loudness.peak - amplitude).abs() < 1e-3);
+        assert!(loudness.true_peak >= loudness.peak);
+        assert!((loudness.true_peak - amplitude).abs() < 1e-2);
+        assert!((loudness.rms - amplitude * std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-3);
+    }
+
+    #[test]
+    fn too_short() {
+        // a gating block lasts 400 ms
+        let frames = sine(1000., -23., 0.39);
+        assert_eq!(integrated(&frames, SAMPLE_RATE), None);
+        assert_eq!(integrated(&[], SAMPLE_RATE), None);
+        assert!(integrated(&sine(1000., -23., 0.4), SAMPLE_RATE).is_some());
+    }
+
+    #[test]
+    fn silent() {
+        let frames = vec![Frame::default(); SAMPLE_RATE as usize];
+        assert_eq!(integrated(&frames, SAMPLE_RATE), None);
+        // below the absolute gate
+        let frames = sine(1000., -80., 1.);
+        assert_eq!(integrated(&frames, SAMPLE_RATE), None);
+        assert_eq!(gain_to(None, -23.), None);
+    }
+}
@@ -4,42 +4,254 @@ pub mod cpal;
 #[cfg(feature = "oboe")]
 pub mod oboe;
 
+pub mod manual;
+pub mod null;
+pub mod supervisor;
+pub mod writer;
+
 use crate::{
+    clock::ClockRecorder,
     mixer::{Mixer, MixerCommand},
+    stats::{StatsRecorder, StatsState},
     LatencyRecorder,
 };
-use anyhow::Result;
+use anyhow::{bail, Result};
 use ringbuf::HeapConsumer;
+use std::{
+    ops::{Deref, DerefMut},
+    ptr,
+    sync::{
+        atomic::{AtomicPtr, AtomicU32, Ordering},
+        Arc,
+    },
+};
 
 pub struct BackendSetup {
     pub(crate) mixer_cons: HeapConsumer<MixerCommand>,
     pub(crate) latency_rec: LatencyRecorder,
+    pub(crate) clock_rec: ClockRecorder,
+    pub(crate) stats_rec: StatsRecorder,
+    pub(crate) stats: Arc<StatsState>,
+    /// The sample rate of the output, reported by the backend when it starts a stream.
+    pub(crate) sample_rate: Arc<AtomicU32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendState {
+    /// No stream exists, either because it was never started or because it was stopped.
+    Stopped,
+    Running,
+    /// The stream is paused but still holds the device.
+    Suspended,
 }
 
 pub trait Backend {
     fn setup(&mut self, setup: BackendSetup) -> Result<()>;
+    /// Builds and plays a new stream, replacing the existing one if any.
     fn start(&mut self) -> Result<()>;
+    /// Destroys the stream and releases the device. Renderers are kept and continue where they
+    /// left off on the next [`Backend::start`].
+    ///
+    /// Backends that can't stop their stream return an error.
+    fn stop(&mut self) -> Result<()> {
+        bail!("stopping is not supported by this backend")
+    }
+    /// Pauses the stream without releasing the device.
+    ///
+    /// Backends that can't pause their stream return an error.
+    fn suspend(&mut self) -> Result<()> {
+        bail!("suspending is not supported by this backend")
+    }
+    /// Resumes a suspended stream.
+    fn resume(&mut self) -> Result<()> {
+        bail!("resuming is not supported by this backend")
+    }
+    /// The default suits backends that can neither stop nor suspend, whose stream runs as soon as
+    /// it is started.
+    fn state(&self) -> BackendState {
+        BackendState::Running
+    }
     fn consume_broken(&self) -> bool;
+
+    /// Whether the device the stream was started on is no longer the one this backend should
+    /// use, e.g. because the system default output changed.
+    fn device_changed(&self) -> bool {
+        false
+    }
+}
+
+type State = (Mixer, LatencyRecorder);
+
+impl BackendSetup {
+    pub(crate) fn into_state(self) -> State {
+        (
+            Mixer::new(0, self.mixer_cons, self.clock_rec, self.stats_rec),
+            self.latency_rec,
+        )
+    }
 }
 
-#[repr(transparent)]
-struct StateCell {
-    _data: (Mixer, LatencyRecorder),
+/// Holds the mixer state while no stream is using it.
+///
+/// A stream takes the state out with [`StateSlot::take`] and moves the returned guard into its
+/// callback, which then has exclusive access without any locking. When the stream is dropped,
+/// the guard puts the state back, so a restarted stream continues with the same renderers. If the
+/// previous callback still holds the state, taking it fails instead of aliasing it.
+struct StateSlot {
+    state: AtomicPtr<State>,
+    stats: Arc<StatsState>,
+    sample_rate: Arc<AtomicU32>,
 }
 
-impl StateCell {
-    pub fn get(&self) -> &mut (Mixer, LatencyRecorder) {
-        #[allow(invalid_reference_casting)]
-        unsafe {
-            &mut *(self as *const StateCell as *const (Mixer, LatencyRecorder) as *mut _)
+impl StateSlot {
+    fn take(self: &Arc<Self>) -> Result<StateGuard> {
+        let ptr = self.state.swap(ptr::null_mut(), Ordering::Acquire);
+        if ptr.is_null() {
+            bail!("audio state is still owned by another stream");
+        }
+        self.resumed();
+        Ok(StateGuard {
+            // SAFETY: non-null pointers in the slot always come from `Box::into_raw`, and the swap
+            // above gives us exclusive ownership
+            state: Some(unsafe { Box::from_raw(ptr) }),
+            slot: Arc::clone(self),
+        })
+    }
+
+    /// Tells the stats that the stream holding the state is about to run again after a pause,
+    /// which [`StateSlot::take`] already does for new streams.
+    fn resumed(&self) {
+        self.stats.mark_restarted();
+    }
+
+    /// Tells the manager the sample rate of the output, for backends that only learn it after
+    /// the state is taken. [`StateGuard::set_sample_rate`] does this for the others.
+    fn report_sample_rate(&self, sample_rate: u32) {
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+    }
+
+    fn put(&self, state: Box<State>) {
+        let old = self.state.swap(Box::into_raw(state), Ordering::Release);
+        if !old.is_null() {
+            // SAFETY: see `take`
+            drop(unsafe { Box::from_raw(old) });
         }
     }
 }
 
-impl From<BackendSetup> for StateCell {
+impl From<BackendSetup> for StateSlot {
     fn from(value: BackendSetup) -> Self {
         Self {
-            _data: (Mixer::new(0, value.mixer_cons), value.latency_rec),
+            stats: Arc::clone(&value.stats),
+            sample_rate: Arc::clone(&value.sample_rate),
+            state: AtomicPtr::new(Box::into_raw(Box::new(value.into_state()))),
+        }
+    }
+}
+
+impl Drop for StateSlot {
+    fn drop(&mut self) {
+        let ptr = *self.state.get_mut();
+        if !ptr.is_null() {
+            // SAFETY: see `take`
+            drop(unsafe { Box::from_raw(ptr) });
+        }
+    }
+}
+
+struct StateGuard {
+    state: Option<Box<State>>,
+    slot: Arc<StateSlot>,
+}
+
+impl StateGuard {
+    /// Sets the sample rate the mixer renders at and reports it to the manager.
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.0.sample_rate = sample_rate;
+        self.slot.report_sample_rate(sample_rate);
+    }
+}
+
+impl Deref for StateGuard {
+    type Target = State;
+
+    fn deref(&self) -> &Self::Target {
+        self.state.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for StateGuard {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.state.as_mut().unwrap()
+    }
+}
+
+impl Drop for StateGuard {
+    fn drop(&mut self) {
+        if let Some(state) = self.state.take() {
+            self.slot.put(state);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::AudioClock;
+    use ringbuf::HeapRb;
+    use std::thread;
+
+    pub(super) fn setup() -> BackendSetup {
+        let (_, cons) = HeapRb::new(4).split();
+        let latency = Arc::<AtomicU32>::default();
+        let stats = Arc::<StatsState>::default();
+        let (_, clock_rec) = AudioClock::new(Arc::clone(&latency));
+        BackendSetup {
+            mixer_cons: cons,
+            latency_rec: LatencyRecorder::new(latency),
+            clock_rec,
+            stats_rec: StatsRecorder::new(Arc::clone(&stats)),
+            stats,
+            sample_rate: Arc::default(),
+        }
+    }
+
+    #[test]
+    fn take_fails_while_guard_is_held() {
+        let slot = Arc::new(StateSlot::from(setup()));
+        let guard = slot.take().unwrap();
+        assert!(slot.take().is_err());
+        drop(guard);
+        assert!(slot.take().is_ok());
+    }
+
+    #[test]
+    fn state_is_put_back() {
+        let slot = Arc::new(StateSlot::from(setup()));
+        let mut guard = slot.take().unwrap();
+        guard.0.sample_rate = 44100;
+        // streams drop their callback on another thread
+        thread::spawn(move || drop(guard)).join().unwrap();
+        assert_eq!(slot.take().unwrap().0.sample_rate, 44100);
+    }
+
+    #[test]
+    fn sample_rate_is_reported() {
+        let setup = setup();
+        let sample_rate = Arc::clone(&setup.sample_rate);
+        let slot = Arc::new(StateSlot::from(setup));
+        let mut guard = slot.take().unwrap();
+        guard.set_sample_rate(44100);
+        assert_eq!(guard.0.sample_rate, 44100);
+        assert_eq!(sample_rate.load(Ordering::Relaxed), 44100);
+    }
+
+    #[test]
+    fn guard_outlives_slot() {
+        let slot = Arc::new(StateSlot::from(setup()));
+        let guard = slot.take().unwrap();
+        drop(slot);
+        // the state goes back into the slot, which is then freed with it
+        drop(guard);
+    }
+}
@@ -1,95 +1,471 @@
-use crate::Backend;
-use anyhow::{anyhow, Context, Result};
+pub use cpal::SampleFormat;
+
+use crate::{stats::StatsState, xrun::XrunKind, Backend, LatencyRecorder};
+use anyhow::{anyhow, bail, Context, Result};
 use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
-    BufferSize, OutputCallbackInfo, Stream, StreamError,
+    BufferSize, Device, OutputCallbackInfo, Sample, SampleRate, Stream, StreamConfig, StreamError,
+    StreamInstant, SupportedBufferSize, SupportedStreamConfig,
 };
 use std::sync::{
     atomic::{AtomicBool, Ordering},
-    Arc,
+    Arc, Mutex,
 };
 
-use super::{BackendSetup, StateCell};
+use super::{BackendSetup, BackendState, StateGuard, StateSlot};
+
+/// Which output device [`CpalBackend`] opens.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum DeviceSelector {
+    #[default]
+    Default,
+    /// The device whose name is exactly this, as reported by [`CpalBackend::output_devices`].
+    Name(String),
+}
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub struct CpalSettings {
     pub buffer_size: Option<u32>,
+    pub device: DeviceSelector,
+    /// Requested stream configuration. Whatever the device doesn't support is replaced by the
+    /// closest supported value, see [`CpalBackend::config_report`] for what was chosen.
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+    pub sample_format: Option<SampleFormat>,
+    /// When the selected device can't be found (e.g. it was unplugged), use the default device
+    /// instead of failing.
+    pub fallback_to_default: bool,
+    /// Apply TPDF dither when the device takes integer samples.
+    pub dither: bool,
+}
+impl Default for CpalSettings {
+    fn default() -> Self {
+        Self {
+            buffer_size: None,
+            device: DeviceSelector::Default,
+            sample_rate: None,
+            channels: None,
+            sample_format: None,
+            fallback_to_default: true,
+            dither: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OutputDeviceInfo {
+    pub name: String,
+    pub is_default: bool,
+    pub configs: Vec<OutputConfigRange>,
+}
+
+#[derive(Debug, Clone)]
+pub struct OutputConfigRange {
+    pub sample_format: SampleFormat,
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    /// Minimum and maximum buffer size in frames, if the platform reports them.
+    pub buffer_size: Option<(u32, u32)>,
+}
+
+/// The stream configuration a started [`CpalBackend`] actually uses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChosenConfig {
+    pub device: String,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub sample_format: SampleFormat,
+    /// Whether every requested setting could be honored.
+    pub exact: bool,
+}
+
+/// Shared view of the [`ChosenConfig`] of a [`CpalBackend`], which stays usable after the
+/// backend is moved into an [`AudioManager`](crate::AudioManager).
+#[derive(Clone, Default)]
+pub struct ConfigReport(Arc<Mutex<Option<ChosenConfig>>>);
+
+impl ConfigReport {
+    /// The configuration of the latest started stream, or `None` if none was started yet.
+    pub fn get(&self) -> Option<ChosenConfig> {
+        self.0.lock().unwrap().clone()
+    }
 }
 
 pub struct CpalBackend {
     settings: CpalSettings,
+    report: ConfigReport,
     stream: Option<Stream>,
+    suspended: bool,
     broken: Arc<AtomicBool>,
-    state: Option<Arc<StateCell>>,
+    state: Option<Arc<StateSlot>>,
+    stats: Option<Arc<StatsState>>,
+    default_device_name: Option<String>,
 }
 
 impl CpalBackend {
     pub fn new(settings: CpalSettings) -> Self {
         Self {
             settings,
+            report: ConfigReport::default(),
             stream: None,
+            suspended: false,
             broken: Arc::default(),
             state: None,
+            stats: None,
+            default_device_name: None,
+        }
+    }
+
+    pub fn config_report(&self) -> ConfigReport {
+        self.report.clone()
+    }
+
+    /// Lists the output devices of the default host together with the configurations they
+    /// support.
+    pub fn output_devices() -> Result<Vec<OutputDeviceInfo>> {
+        let host = cpal::default_host();
+        let default_name = host.default_output_device().and_then(|it| it.name().ok());
+        let mut result = Vec::new();
+        for device in host
+            .output_devices()
+            .context("cannot enumerate output devices")?
+        {
+            let Ok(name) = device.name() else {
+                continue;
+            };
+            let configs = match device.supported_output_configs() {
+                Ok(configs) => configs
+                    .map(|it| OutputConfigRange {
+                        sample_format: it.sample_format(),
+                        channels: it.channels(),
+                        min_sample_rate: it.min_sample_rate().0,
+                        max_sample_rate: it.max_sample_rate().0,
+                        buffer_size: match *it.buffer_size() {
+                            SupportedBufferSize::Range { min, max } => Some((min, max)),
+                            SupportedBufferSize::Unknown => None,
+                        },
+                    })
+                    .collect(),
+                Err(_) => Vec::new(),
+            };
+            result.push(OutputDeviceInfo {
+                is_default: default_name.as_ref() == Some(&name),
+                name,
+                configs,
+            });
+        }
+        Ok(result)
+    }
+
+    fn find_device(&self) -> Result<Device> {
+        let host = cpal::default_host();
+        if let DeviceSelector::Name(name) = &self.settings.device {
+            let device = host
+                .output_devices()
+                .context("cannot enumerate output devices")?
+                .find(|it| it.name().ok().as_ref() == Some(name));
+            match device {
+                Some(device) => return Ok(device),
+                None if self.settings.fallback_to_default => {
+                    self.warn(format!(
+                        "output device `{name}` is not found, falling back to default"
+                    ));
+                }
+                None => return Err(anyhow!("output device `{name}` is not found")),
+            }
+        }
+        host.default_output_device()
+            .ok_or_else(|| anyhow!("no default output device is found"))
+    }
+
+    /// Picks the supported config closest to the requested one. A matching sample rate matters
+    /// most since anything else means resampling every clip, then the channel count, then the
+    /// sample format. Unrequested values default to the device's default config.
+    fn find_config(&self, device: &Device) -> Result<SupportedStreamConfig> {
+        let CpalSettings {
+            sample_rate,
+            channels,
+            sample_format,
+            ..
+        } = self.settings;
+        let default = device
+            .default_output_config()
+            .context("cannot get output config")?;
+        if sample_rate.is_none() && channels.is_none() && sample_format.is_none() {
+            return Ok(default);
+        }
+        let wanted_rate = sample_rate.unwrap_or(default.sample_rate().0);
+        let wanted_channels = channels.unwrap_or(default.channels());
+        let wanted_format = sample_format.unwrap_or(SampleFormat::F32);
+        let found = device
+            .supported_output_configs()
+            .context("cannot get supported output configs")?
+            .map(|it| {
+                let rate = wanted_rate.clamp(it.min_sample_rate().0, it.max_sample_rate().0);
+                let key = (
+                    rate.abs_diff(wanted_rate),
+                    // extra channels are left silent, missing ones are lost
+                    it.channels() < wanted_channels,
+                    it.channels().abs_diff(wanted_channels),
+                    it.sample_format() != wanted_format,
+                    // f32 needs no conversion, so prefer it over integer formats
+                    it.sample_format() != SampleFormat::F32,
+                );
+                (key, it.with_sample_rate(SampleRate(rate)))
+            })
+            .min_by_key(|(key, _)| *key)
+            .map(|(_, it)| it);
+        match found {
+            Some(config) => Ok(config),
+            None => {
+                self.warn("device reports no supported output config, using default".to_owned());
+                Ok(default)
+            }
+        }
+    }
+
+    fn warn(&self, message: String) {
+        if let Some(stats) = &self.stats {
+            stats.xruns.push(XrunKind::Warning(message));
         }
     }
 }
 
 impl Backend for CpalBackend {
     fn setup(&mut self, setup: BackendSetup) -> Result<()> {
+        self.stats = Some(Arc::clone(&setup.stats));
         self.state = Some(Arc::new(setup.into()));
         Ok(())
     }
 
     fn start(&mut self) -> Result<()> {
-        let host = cpal::default_host();
-        let device = host
-            .default_output_device()
-            .ok_or_else(|| anyhow!("no default output device is found"))?;
-        let mut config = device
-            .default_output_config()
-            .context("cannot get output config")?
-            .config();
+        // look the device up first, so that a missing device leaves the running stream alone
+        let device = self.find_device()?;
+        let supported = self.find_config(&device)?;
+        // the old stream must be gone before its replacement starts rendering the same state
+        self.stop()?;
+
+        self.default_device_name = match self.settings.device {
+            DeviceSelector::Default => device.name().ok(),
+            DeviceSelector::Name(_) => None,
+        };
+        let (sample_rate, channels, sample_format) = (
+            supported.sample_rate().0,
+            supported.channels(),
+            supported.sample_format(),
+        );
+        let chosen = ChosenConfig {
+            device: device.name().unwrap_or_default(),
+            sample_rate,
+            channels,
+            sample_format,
+            exact: self.settings.sample_rate.unwrap_or(sample_rate) == sample_rate
+                && self.settings.channels.unwrap_or(channels) == channels
+                && self.settings.sample_format.unwrap_or(sample_format) == sample_format,
+        };
+        if !chosen.exact {
+            self.warn(format!(
+                "requested output config (sample rate {:?}, channels {:?}, format {:?}) is not supported, using {chosen:?}",
+                self.settings.sample_rate, self.settings.channels, self.settings.sample_format,
+            ));
+        }
+        let mut config = supported.config();
         config.buffer_size = self
             .settings
             .buffer_size
-            .map_or(BufferSize::Default, |it| BufferSize::Fixed(it));
+            .map_or(BufferSize::Default, BufferSize::Fixed);
 
         let broken = Arc::clone(&self.broken);
-        let error_callback = move |err| {
-            eprintln!("audio error: {err:?}");
-            if matches!(err, StreamError::DeviceNotAvailable) {
+        let stats = Arc::clone(self.stats.as_ref().context("backend is not set up")?);
+        let error_callback = move |err| match err {
+            StreamError::DeviceNotAvailable => {
+                stats.xruns.push(XrunKind::StreamError(err.to_string()));
                 broken.store(true, Ordering::Relaxed);
             }
+            err => stats.xruns.push(XrunKind::StreamError(err.to_string())),
         };
-        let state = Arc::clone(self.state.as_ref().unwrap());
-        state.get().0.sample_rate = config.sample_rate.0;
-        let channels = config.channels;
-        let stream = device.build_output_stream(
-            &config,
-            move |data: &mut [f32], info: &OutputCallbackInfo| {
-                let (mixer, rec) = state.get();
-                match channels {
-                    1 => {
-                        mixer.render_mono(data);
-                    }
-                    _ => {
-                        mixer.render_stereo(data);
-                    }
-                }
-                let ts = info.timestamp();
-                if let Some(delay) = ts.playback.duration_since(&ts.callback) {
-                    rec.push(delay.as_secs_f32());
-                }
-            },
-            error_callback,
-        )
+        let mut state = self
+            .state
+            .as_ref()
+            .context("backend is not set up")?
+            .take()?;
+        state.set_sample_rate(config.sample_rate.0);
+        let max_frames = max_period_frames(&config, &supported);
+        state.0.prepare(max_frames);
+        let dither = self.settings.dither;
+        let stream = match supported.sample_format() {
+            SampleFormat::F32 => {
+                let channels = config.channels;
+                let mut clock = CallbackClock::default();
+                device.build_output_stream(
+                    &config,
+                    move |data: &mut [f32], info: &OutputCallbackInfo| {
+                        let (mixer, rec) = &mut *state;
+                        mixer.render_timed(channels, data, clock.timestamp(info));
+                        push_latency(rec, info);
+                    },
+                    error_callback,
+                )
+            }
+            SampleFormat::I16 => build_converting_stream::<i16>(
+                &device,
+                &config,
+                state,
+                max_frames,
+                dither,
+                error_callback,
+            ),
+            SampleFormat::U16 => build_converting_stream::<u16>(
+                &device,
+                &config,
+                state,
+                max_frames,
+                dither,
+                error_callback,
+            ),
+        }
         .context("failed to build stream")?;
         stream.play()?;
         self.stream = Some(stream);
+        *self.report.0.lock().unwrap() = Some(chosen);
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.stream = None;
+        self.suspended = false;
+        Ok(())
+    }
+
+    fn suspend(&mut self) -> Result<()> {
+        let Some(stream) = &self.stream else {
+            bail!("stream is not started");
+        };
+        stream.pause().context("failed to pause stream")?;
+        self.suspended = true;
         Ok(())
     }
 
+    fn resume(&mut self) -> Result<()> {
+        let Some(stream) = &self.stream else {
+            bail!("stream is not started");
+        };
+        if let Some(slot) = &self.state {
+            slot.resumed();
+        }
+        stream.play().context("failed to resume stream")?;
+        self.suspended = false;
+        Ok(())
+    }
+
+    fn state(&self) -> BackendState {
+        match (&self.stream, self.suspended) {
+            (None, _) => BackendState::Stopped,
+            (Some(_), false) => BackendState::Running,
+            (Some(_), true) => BackendState::Suspended,
+        }
+    }
+
     fn consume_broken(&self) -> bool {
         self.broken.fetch_and(false, Ordering::Relaxed)
     }
+
+    fn device_changed(&self) -> bool {
+        let Some(name) = &self.default_device_name else {
+            return false;
+        };
+        cpal::default_host()
+            .default_output_device()
+            .and_then(|it| it.name().ok())
+            .is_some_and(|it| &it != name)
+    }
+}
+
+/// Upper bound of the frames a callback may ask for, as far as the platform tells. Callbacks
+/// beyond it are still rendered, in several passes.
+fn max_period_frames(config: &StreamConfig, supported: &SupportedStreamConfig) -> usize {
+    /// Some hosts report huge maximum buffer sizes that are never used by default.
+    const MAX_DEFAULT_FRAMES: u32 = 8192;
+    let frames = match (&config.buffer_size, supported.buffer_size()) {
+        (BufferSize::Fixed(frames), _) => *frames,
+        (BufferSize::Default, SupportedBufferSize::Range { max, .. }) => {
+            (*max).min(MAX_DEFAULT_FRAMES)
+        }
+        (BufferSize::Default, SupportedBufferSize::Unknown) => MAX_DEFAULT_FRAMES,
+    };
+    frames as usize
+}
+
+fn push_latency(rec: &mut LatencyRecorder, info: &OutputCallbackInfo) {
+    let ts = info.timestamp();
+    if let Some(delay) = ts.playback.duration_since(&ts.callback) {
+        rec.push(delay.as_secs_f32());
+    }
+}
+
+/// Builds a stream for a device with an integer sample format. The mixer renders into an f32
+/// scratch buffer of `max_frames` frames which is then converted into the device format.
+fn build_converting_stream<T: Sample + Send + 'static>(
+    device: &Device,
+    config: &StreamConfig,
+    mut state: StateGuard,
+    max_frames: usize,
+    dither: bool,
+    error_callback: impl FnMut(StreamError) + Send + 'static,
+) -> Result<Stream, cpal::BuildStreamError> {
+    let channels = config.channels;
+    let mut buffer = vec![0.; max_frames.max(1) * (channels as usize).max(1)];
+    let mut rng = Xorshift32(0x9E37_79B9);
+    let mut clock = CallbackClock::default();
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], info: &OutputCallbackInfo| {
+            let (mixer, rec) = &mut *state;
+            let timestamp = clock.timestamp(info);
+            let pass_frames = buffer.len() / channels as usize;
+            // callbacks longer than the buffer are rendered in several passes
+            for (i, data) in data.chunks_mut(buffer.len()).enumerate() {
+                let buffer = &mut buffer[..data.len()];
+                let offset = (i * pass_frames) as f64 / mixer.sample_rate.max(1) as f64;
+                mixer.render_timed(channels, buffer, timestamp.map(|it| it + offset));
+                for (out, &sample) in data.iter_mut().zip(buffer.iter()) {
+                    let sample = if dither {
+                        // both supported integer formats are 16-bit
+                        sample + (rng.next_f32() - rng.next_f32()) / 32768.
+                    } else {
+                        sample
+                    };
+                    *out = T::from(&sample.clamp(-1., 1.));
+                }
+            }
+            push_latency(rec, info);
+        },
+        error_callback,
+    )
+}
+
+/// Times callbacks from the timestamps the host gives them, relative to the first one.
+#[derive(Default)]
+struct CallbackClock(Option<StreamInstant>);
+
+impl CallbackClock {
+    fn timestamp(&mut self, info: &OutputCallbackInfo) -> Option<f64> {
+        let callback = info.timestamp().callback;
+        let first = *self.0.get_or_insert(callback);
+        callback.duration_since(&first).map(|it| it.as_secs_f64())
+    }
+}
+
+/// Cheap PRNG for dither noise, which must not allocate or lock in the audio callback.
+struct Xorshift32(u32);
+
+impl Xorshift32 {
+    #[inline(always)]
+    fn next_f32(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
 }
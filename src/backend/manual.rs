@@ -0,0 +1,220 @@
+use super::{BackendSetup, BackendState, StateGuard, StateSlot};
+use crate::{Backend, Frame};
+use anyhow::{bail, Context, Result};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
+#[derive(Debug, Clone)]
+pub struct ManualSettings {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub block_size: usize,
+}
+impl Default for ManualSettings {
+    fn default() -> Self {
+        Self {
+            sample_rate: 48000,
+            channels: 2,
+            block_size: 512,
+        }
+    }
+}
+
+/// A backend that never renders on its own. Audio is pulled block by block on the calling thread
+/// through a [`ManualHandle`], which makes the output fully deterministic.
+///
+/// Like the other backends, a started stream takes the mixer state out of its slot and gives it
+/// back when it is stopped, with the handle playing the part of the stream callback.
+pub struct ManualBackend {
+    handle: ManualHandle,
+    slot: Option<Arc<StateSlot>>,
+    state: BackendState,
+}
+
+impl ManualBackend {
+    pub fn new(settings: ManualSettings) -> Self {
+        Self {
+            handle: ManualHandle {
+                state: Arc::default(),
+                broken: Arc::default(),
+                running: Arc::default(),
+                settings,
+            },
+            slot: None,
+            state: BackendState::Stopped,
+        }
+    }
+
+    /// Returns a handle that can be used to render after the backend is moved into an
+    /// [`AudioManager`](crate::AudioManager).
+    pub fn handle(&self) -> ManualHandle {
+        self.handle.clone()
+    }
+
+    fn set_state(&mut self, state: BackendState) {
+        self.state = state;
+        self.handle
+            .running
+            .store(state == BackendState::Running, Ordering::Relaxed);
+    }
+}
+
+impl Backend for ManualBackend {
+    fn setup(&mut self, setup: BackendSetup) -> Result<()> {
+        self.slot = Some(Arc::new(setup.into()));
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        let slot = self.slot.as_ref().context("backend is not set up")?;
+        // the old stream must be gone before its replacement takes the same state
+        let mut guard = self.handle.state.lock().unwrap();
+        *guard = None;
+        let mut state = slot.take()?;
+        state.set_sample_rate(self.handle.settings.sample_rate);
+        state.0.prepare(self.handle.settings.block_size);
+        *guard = Some(state);
+        drop(guard);
+        self.set_state(BackendState::Running);
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        *self.handle.state.lock().unwrap() = None;
+        self.set_state(BackendState::Stopped);
+        Ok(())
+    }
+
+    fn suspend(&mut self) -> Result<()> {
+        if self.state == BackendState::Stopped {
+            bail!("stream is not started");
+        }
+        self.set_state(BackendState::Suspended);
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        if self.state == BackendState::Stopped {
+            bail!("stream is not started");
+        }
+        if let Some(slot) = &self.slot {
+            slot.resumed();
+        }
+        self.set_state(BackendState::Running);
+        Ok(())
+    }
+
+    fn state(&self) -> BackendState {
+        self.state
+    }
+
+    fn consume_broken(&self) -> bool {
+        self.handle.broken.fetch_and(false, Ordering::Relaxed)
+    }
+}
+
+#[derive(Clone)]
+pub struct ManualHandle {
+    state: Arc<Mutex<Option<StateGuard>>>,
+    broken: Arc<AtomicBool>,
+    running: Arc<AtomicBool>,
+    settings: ManualSettings,
+}
+
+impl ManualHandle {
+    /// Renders `frames` frames using the settings the backend was created with.
+    pub fn render(&self, frames: usize) -> Vec<Frame> {
+        self.render_with(frames, &self.settings)
+    }
+
+    /// Renders `frames` frames, splitting them into blocks of `settings.block_size` frames. Each
+    /// block is one mixer callback, so commands sent in between are only picked up at block
+    /// boundaries.
+    ///
+    /// Mono output is duplicated to both sides of the returned frames; for more than two channels
+    /// only the first two are returned. Unless the backend is running, the output is silence and
+    /// renderers don't advance.
+    pub fn render_with(&self, frames: usize, settings: &ManualSettings) -> Vec<Frame> {
+        let channels = settings.channels.max(1) as usize;
+        self.render_interleaved(frames, settings)
+            .chunks_exact(channels)
+            .map(|it| match it {
+                [mono] => Frame(*mono, *mono),
+                [left, right, ..] => Frame(*left, *right),
+                [] => unreachable!(),
+            })
+            .collect()
+    }
+
+    /// Like [`ManualHandle::render_with`], but returns the interleaved output with all of its
+    /// `settings.channels` channels.
+    pub fn render_interleaved(&self, frames: usize, settings: &ManualSettings) -> Vec<f32> {
+        let channels = settings.channels.max(1) as usize;
+        let mut result = vec![0.; frames * channels];
+        if !self.running.load(Ordering::Relaxed) {
+            return result;
+        }
+        let mut guard = self.state.lock().unwrap();
+        let Some(state) = guard.as_mut() else {
+            return result;
+        };
+        state.set_sample_rate(settings.sample_rate);
+        let (mixer, _) = &mut **state;
+
+        let block_size = settings.block_size.max(1);
+        for block in result.chunks_mut(block_size * channels) {
+            mixer.render(channels as u16, block);
+        }
+        result
+    }
+
+    /// Simulates a stream failure: the backend reports itself broken until the next
+    /// [`Backend::consume_broken`].
+    pub fn mark_broken(&self) {
+        self.broken.store(true, Ordering::Relaxed);
+    }
+
+    /// Applies pending mixer commands (e.g. added renderers) without rendering anything. Does
+    /// nothing while the stream is stopped.
+    pub(crate) fn sync(&self) {
+        if let Some(state) = self.state.lock().unwrap().as_mut() {
+            state.0.consume_commands();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{tests::setup, State};
+
+    #[test]
+    fn manual_restart() {
+        let mut backend = ManualBackend::new(ManualSettings::default());
+        backend.setup(setup()).unwrap();
+        assert!(backend.slot.as_ref().unwrap().take().is_ok());
+
+        // the state is boxed once and moved around by pointer
+        let address = |backend: &ManualBackend| {
+            let guard = backend.handle.state.lock().unwrap();
+            &**guard.as_ref().unwrap() as *const State
+        };
+        backend.start().unwrap();
+        let state = address(&backend);
+        // the running stream owns the state, but restarting hands it over
+        assert!(backend.slot.as_ref().unwrap().take().is_err());
+        backend.start().unwrap();
+        assert_eq!(backend.state(), BackendState::Running);
+        assert_eq!(address(&backend), state);
+
+        backend.stop().unwrap();
+        let guard = backend.slot.as_ref().unwrap().take().unwrap();
+        assert_eq!(&*guard as *const State, state);
+        assert!(backend.start().is_err());
+        drop(guard);
+        backend.start().unwrap();
+        assert_eq!(backend.handle().render(16).len(), 16);
+    }
+}
@@ -0,0 +1,179 @@
+use super::{BackendSetup, BackendState, StateSlot};
+use crate::Backend;
+use anyhow::{bail, Context, Result};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// How many buffers the render thread may fall behind before it gives up catching up and
+/// resynchronizes with the wall clock.
+const MAX_LAG_BUFFERS: u32 = 8;
+
+#[derive(Debug, Clone)]
+pub struct NullSettings {
+    pub sample_rate: u32,
+    pub buffer_size: u32,
+    pub channels: u16,
+}
+impl Default for NullSettings {
+    fn default() -> Self {
+        Self {
+            sample_rate: 48000,
+            buffer_size: 512,
+            channels: 2,
+        }
+    }
+}
+
+/// A backend without any audio device. The mixer is driven from a dedicated thread that renders
+/// one buffer per buffer period of wall-clock time and discards the output.
+pub struct NullBackend {
+    settings: NullSettings,
+    state: Option<Arc<StateSlot>>,
+    thread: Option<(Arc<AtomicBool>, JoinHandle<()>)>,
+    suspended: bool,
+}
+
+impl NullBackend {
+    pub fn new(settings: NullSettings) -> Self {
+        Self {
+            settings,
+            state: None,
+            thread: None,
+            suspended: false,
+        }
+    }
+
+    fn stop_thread(&mut self) {
+        if let Some((stop, handle)) = self.thread.take() {
+            stop.store(true, Ordering::Relaxed);
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Sleeps between buffers so that they are produced at the rate a device would consume them.
+pub(super) struct Pacer {
+    period: Duration,
+    origin: Instant,
+    deadline: Instant,
+}
+
+impl Pacer {
+    pub fn new(buffer_size: u32, sample_rate: u32) -> Self {
+        let now = Instant::now();
+        Self {
+            period: Duration::from_secs_f64(buffer_size as f64 / sample_rate as f64),
+            origin: now,
+            deadline: now,
+        }
+    }
+
+    /// When the current buffer is due, in seconds since the pacer was created.
+    pub fn timestamp(&self) -> f64 {
+        (self.deadline - self.origin).as_secs_f64()
+    }
+
+    /// Waits until the next buffer is due.
+    pub fn wait(&mut self) {
+        self.deadline += self.period;
+        let now = Instant::now();
+        if self.deadline > now {
+            thread::sleep(self.deadline - now);
+        } else if now - self.deadline > self.period * MAX_LAG_BUFFERS {
+            self.deadline = now;
+        }
+    }
+}
+
+impl Backend for NullBackend {
+    fn setup(&mut self, setup: BackendSetup) -> Result<()> {
+        self.state = Some(Arc::new(setup.into()));
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        self.stop_thread();
+        self.suspended = false;
+
+        let NullSettings {
+            sample_rate,
+            buffer_size,
+            channels,
+        } = self.settings.clone();
+        let mut state = self
+            .state
+            .as_ref()
+            .context("backend is not set up")?
+            .take()?;
+        state.set_sample_rate(sample_rate);
+        state.0.prepare(buffer_size as usize);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = thread::Builder::new()
+            .name("sasa-null".to_owned())
+            .spawn({
+                let stop = Arc::clone(&stop);
+                move || {
+                    let mut pacer = Pacer::new(buffer_size, sample_rate);
+                    let mut data = vec![0.; buffer_size as usize * channels as usize];
+                    while !stop.load(Ordering::Relaxed) {
+                        state
+                            .0
+                            .render_timed(channels, &mut data, Some(pacer.timestamp()));
+                        pacer.wait();
+                    }
+                }
+            })
+            .context("failed to spawn render thread")?;
+        self.thread = Some((stop, handle));
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.stop_thread();
+        self.suspended = false;
+        Ok(())
+    }
+
+    fn suspend(&mut self) -> Result<()> {
+        if self.thread.is_none() {
+            bail!("stream is not started");
+        }
+        self.stop_thread();
+        self.suspended = true;
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        if !self.suspended {
+            bail!("stream is not suspended");
+        }
+        self.start()
+    }
+
+    fn state(&self) -> BackendState {
+        if self.thread.is_some() {
+            BackendState::Running
+        } else if self.suspended {
+            BackendState::Suspended
+        } else {
+            BackendState::Stopped
+        }
+    }
+
+    fn consume_broken(&self) -> bool {
+        false
+    }
+}
+
+impl Drop for NullBackend {
+    fn drop(&mut self) {
+        self.stop_thread();
+    }
+}
@@ -1,11 +1,11 @@
 pub use oboe::{PerformanceMode, Usage};
 
-use super::{BackendSetup, StateCell};
-use crate::Backend;
-use anyhow::Result;
+use super::{BackendSetup, BackendState, StateGuard, StateSlot};
+use crate::{xrun::XrunKind, Backend};
+use anyhow::{bail, Context, Result};
 use oboe::{
-    AudioOutputCallback, AudioOutputStreamSafe, AudioStream, AudioStreamAsync, AudioStreamBuilder,
-    DataCallbackResult, Output, SharingMode, Stereo,
+    AudioOutputCallback, AudioOutputStreamSafe, AudioStream, AudioStreamAsync, AudioStreamBase,
+    AudioStreamBuilder, DataCallbackResult, Output, SharingMode, Stereo,
 };
 use std::sync::{
     atomic::{AtomicBool, Ordering},
@@ -33,7 +33,8 @@ impl Default for OboeSettings {
 pub struct OboeBackend {
     settings: OboeSettings,
     stream: Option<AudioStreamAsync<Output, OboeCallback>>,
-    state: Option<Arc<StateCell>>,
+    suspended: bool,
+    state: Option<Arc<StateSlot>>,
     broken: Arc<AtomicBool>,
     buffer_pool: Vec<Vec<f32>>,
 }
@@ -43,6 +44,7 @@ impl OboeBackend {
         Self {
             settings,
             stream: None,
+            suspended: false,
             state: None,
             broken: Arc::default(),
             buffer_pool: Vec::new(),
@@ -57,6 +59,9 @@ impl Backend for OboeBackend {
     }
 
     fn start(&mut self) -> Result<()> {
+        self.stop()?;
+
+        let slot = self.state.as_ref().context("backend is not set up")?;
         let mut stream = AudioStreamBuilder::default()
             .set_usage(self.settings.usage)
             .set_performance_mode(self.settings.performance_mode)
@@ -64,36 +69,81 @@ impl Backend for OboeBackend {
             .set_format::<f32>()
             .set_channel_count::<Stereo>()
             .set_callback(OboeCallback::new(
-                Arc::clone(self.state.as_ref().unwrap()),
+                slot.take()?,
                 Arc::clone(&self.broken),
                 self.settings.buffer_size,
             ))
             .open_stream()
-            .unwrap();
+            .context("failed to open stream")?;
+        // the device picks the rate when the stream is opened
+        slot.report_sample_rate(stream.get_sample_rate() as u32);
         stream.start()?;
         self.stream = Some(stream);
         Ok(())
     }
 
+    fn stop(&mut self) -> Result<()> {
+        if let Some(mut stream) = self.stream.take() {
+            stream.stop()?;
+        }
+        self.suspended = false;
+        Ok(())
+    }
+
+    fn suspend(&mut self) -> Result<()> {
+        let Some(stream) = &mut self.stream else {
+            bail!("stream is not started");
+        };
+        stream.pause()?;
+        self.suspended = true;
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        let Some(stream) = &mut self.stream else {
+            bail!("stream is not started");
+        };
+        if let Some(slot) = &self.state {
+            slot.resumed();
+        }
+        stream.start()?;
+        self.suspended = false;
+        Ok(())
+    }
+
+    fn state(&self) -> BackendState {
+        match (&self.stream, self.suspended) {
+            (None, _) => BackendState::Stopped,
+            (Some(_), false) => BackendState::Running,
+            (Some(_), true) => BackendState::Suspended,
+        }
+    }
+
     fn consume_broken(&self) -> bool {
         self.broken.fetch_and(false, Ordering::Relaxed)
     }
 }
 
 struct OboeCallback {
-    state: Arc<StateCell>,
+    state: StateGuard,
     broken: Arc<AtomicBool>,
     buffer_size: Option<u32>,
 }
 
 impl OboeCallback {
-    pub fn new(state: Arc<StateCell>, broken: Arc<AtomicBool>, buffer_size: Option<u32>) -> Self {
+    pub fn new(state: StateGuard, broken: Arc<AtomicBool>, buffer_size: Option<u32>) -> Self {
         Self {
             state,
             broken,
             buffer_size,
         }
     }
+
+    fn error(&self, error: oboe::Error) {
+        let kind = XrunKind::StreamError(format!("{error:?}"));
+        self.state.slot.stats.xruns.push(kind);
+        self.broken.store(true, Ordering::Relaxed);
+    }
 }
 
 impl AudioOutputCallback for OboeCallback {
@@ -116,14 +166,14 @@ impl AudioOutputCallback for OboeCallback {
             }
         }
 
-        let (mixer, rec) = self.state.get();
+        let (mixer, rec) = &mut *self.state;
         if let Ok(latency) = stream.calculate_latency_millis() {
             rec.push((latency / 1000.) as f32);
         }
         mixer.sample_rate = stream.get_sample_rate() as u32;
         let raw = frames.as_mut_ptr();
         
-        mixer.render_stereo(unsafe {
+        mixer.render(2, unsafe {
             std::slice::from_raw_parts_mut(raw as *mut f32, frames.len() * 2)
         });
 
@@ -135,8 +185,7 @@ impl AudioOutputCallback for OboeCallback {
         _audio_stream: &mut dyn oboe::AudioOutputStreamSafe,
         error: oboe::Error,
     ) {
-        eprintln!("audio error: {error:?}");
-        self.broken.store(true, Ordering::Relaxed);
+        self.error(error);
     }
 
     fn on_error_after_close(
@@ -144,8 +193,7 @@ impl AudioOutputCallback for OboeCallback {
         _audio_stream: &mut dyn oboe::AudioOutputStreamSafe,
         error: oboe::Error,
     ) {
-        eprintln!("audio error: {error:?}");
-        self.broken.store(true, Ordering::Relaxed);
+        self.error(error);
     }
 }
 
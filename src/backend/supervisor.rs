@@ -0,0 +1,282 @@
+use super::{BackendSetup, BackendState};
+use crate::Backend;
+use anyhow::{anyhow, Context, Result};
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone)]
+pub struct SupervisorSettings {
+    /// How often the inner backend is checked for a lost or replaced device.
+    pub poll_interval: Duration,
+    /// Rebuild the stream when [`Backend::device_changed`] reports that the device it follows
+    /// (usually the system default output) has changed.
+    pub follow_default_device: bool,
+    pub event_buffer_size: usize,
+}
+impl Default for SupervisorSettings {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_millis(500),
+            follow_default_device: true,
+            event_buffer_size: 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryReason {
+    DeviceLost,
+    DeviceChanged,
+}
+
+#[derive(Debug, Clone)]
+pub enum SupervisorEvent {
+    Recovered(RecoveryReason),
+    /// Restarting failed; it will be retried on the next poll.
+    RecoveryFailed(RecoveryReason, String),
+}
+
+/// Recovery events reported by a [`SupervisedBackend`], oldest first.
+pub struct SupervisorEvents {
+    cons: HeapConsumer<SupervisorEvent>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl SupervisorEvents {
+    /// Number of events lost because the buffer was full when they happened.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Iterator for SupervisorEvents {
+    type Item = SupervisorEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cons.pop()
+    }
+}
+
+type BackendFactory = Box<dyn FnOnce() -> Box<dyn Backend> + Send>;
+
+#[derive(Clone, Copy)]
+enum Operation {
+    Start,
+    Stop,
+    Suspend,
+    Resume,
+}
+
+struct Command(Operation, SyncSender<Result<()>>);
+
+/// Wraps another backend and restarts its stream automatically when the device is lost or
+/// replaced, so the application doesn't need to call
+/// [`AudioManager::recover_if_needed`](crate::AudioManager::recover_if_needed).
+///
+/// The inner backend is created by `factory` on a dedicated supervisor thread and lives there
+/// until this backend is dropped, so it doesn't need to be `Send`. Since restarting reuses the
+/// mixer state handed to the inner backend in [`Backend::setup`], all live renderers keep playing
+/// after a recovery.
+pub struct SupervisedBackend {
+    factory: Option<BackendFactory>,
+    settings: SupervisorSettings,
+    events: Option<(HeapProducer<SupervisorEvent>, Arc<AtomicU64>)>,
+    failed: Arc<AtomicBool>,
+    state: Arc<Mutex<BackendState>>,
+    thread: Option<(Sender<Command>, JoinHandle<()>)>,
+}
+
+impl SupervisedBackend {
+    pub fn new<B: Backend + 'static>(
+        factory: impl FnOnce() -> B + Send + 'static,
+        settings: SupervisorSettings,
+    ) -> (Self, SupervisorEvents) {
+        let (prod, cons) = HeapRb::new(settings.event_buffer_size).split();
+        let dropped = Arc::<AtomicU64>::default();
+        (
+            Self {
+                factory: Some(Box::new(move || Box::new(factory()))),
+                settings,
+                events: Some((prod, Arc::clone(&dropped))),
+                failed: Arc::default(),
+                state: Arc::new(Mutex::new(BackendState::Stopped)),
+                thread: None,
+            },
+            SupervisorEvents { cons, dropped },
+        )
+    }
+
+    fn send(&self, operation: Operation) -> Result<()> {
+        let (sender, _) = self
+            .thread
+            .as_ref()
+            .ok_or_else(|| anyhow!("backend is not set up"))?;
+        let (tx, rx) = mpsc::sync_channel(1);
+        sender
+            .send(Command(operation, tx))
+            .map_err(|_| anyhow!("supervisor thread is gone"))?;
+        rx.recv().context("supervisor thread is gone")?
+    }
+}
+
+impl Backend for SupervisedBackend {
+    fn setup(&mut self, setup: BackendSetup) -> Result<()> {
+        let factory = self
+            .factory
+            .take()
+            .ok_or_else(|| anyhow!("backend is already set up"))?;
+        let (events, dropped) = self.events.take().unwrap();
+        let supervisor = Supervisor {
+            settings: self.settings.clone(),
+            events,
+            dropped,
+            failed: Arc::clone(&self.failed),
+            state: Arc::clone(&self.state),
+        };
+        let (setup_tx, setup_rx) = mpsc::sync_channel(1);
+        let (tx, rx) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name("sasa-supervisor".to_owned())
+            .spawn(move || {
+                let mut backend = factory();
+                let result = backend.setup(setup);
+                let ok = result.is_ok();
+                let _ = setup_tx.send(result);
+                if ok {
+                    supervisor.run(backend, rx);
+                }
+            })
+            .context("failed to spawn supervisor thread")?;
+        setup_rx.recv().context("supervisor thread is gone")??;
+        self.thread = Some((tx, handle));
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        self.send(Operation::Start)
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.send(Operation::Stop)
+    }
+
+    fn suspend(&mut self) -> Result<()> {
+        self.send(Operation::Suspend)
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        self.send(Operation::Resume)
+    }
+
+    fn state(&self) -> BackendState {
+        *self.state.lock().unwrap()
+    }
+
+    /// Only reports failed recoveries, since successful ones are handled internally.
+    fn consume_broken(&self) -> bool {
+        self.failed.fetch_and(false, Ordering::Relaxed)
+    }
+}
+
+impl Drop for SupervisedBackend {
+    fn drop(&mut self) {
+        if let Some((sender, handle)) = self.thread.take() {
+            drop(sender);
+            let _ = handle.join();
+        }
+    }
+}
+
+struct Supervisor {
+    settings: SupervisorSettings,
+    events: HeapProducer<SupervisorEvent>,
+    dropped: Arc<AtomicU64>,
+    failed: Arc<AtomicBool>,
+    state: Arc<Mutex<BackendState>>,
+}
+
+impl Supervisor {
+    fn run(mut self, mut backend: Box<dyn Backend>, commands: mpsc::Receiver<Command>) {
+        // the state requested by the application, which recoveries restore
+        let mut target = BackendState::Stopped;
+        let mut pending = None;
+        // checks are scheduled on a deadline, so a steady stream of commands can't postpone them
+        let mut next_check = Instant::now() + self.settings.poll_interval;
+        loop {
+            let timeout = next_check.saturating_duration_since(Instant::now());
+            match commands.recv_timeout(timeout) {
+                Ok(Command(operation, reply)) => {
+                    let result = match operation {
+                        Operation::Start => backend.start(),
+                        Operation::Stop => backend.stop(),
+                        Operation::Suspend => backend.suspend(),
+                        Operation::Resume => backend.resume(),
+                    };
+                    if result.is_ok() {
+                        target = backend.state();
+                        pending = None;
+                    }
+                    *self.state.lock().unwrap() = backend.state();
+                    let _ = reply.send(result);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            if Instant::now() >= next_check {
+                next_check = Instant::now() + self.settings.poll_interval;
+                if let Some(event) = self.check(&mut *backend, target, &mut pending) {
+                    *self.state.lock().unwrap() = backend.state();
+                    if self.events.push(event).is_err() {
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Restarts the stream if needed, bringing it back into the state it was in before. A
+    /// stopped stream is never restarted.
+    fn check(
+        &self,
+        backend: &mut dyn Backend,
+        target: BackendState,
+        pending: &mut Option<RecoveryReason>,
+    ) -> Option<SupervisorEvent> {
+        if target == BackendState::Stopped {
+            return None;
+        }
+        let reason = if backend.consume_broken() {
+            RecoveryReason::DeviceLost
+        } else if self.settings.follow_default_device && backend.device_changed() {
+            RecoveryReason::DeviceChanged
+        } else {
+            (*pending)?
+        };
+        let result = backend.start().and_then(|_| {
+            if target == BackendState::Suspended {
+                backend.suspend()
+            } else {
+                Ok(())
+            }
+        });
+        Some(match result {
+            Ok(()) => {
+                *pending = None;
+                SupervisorEvent::Recovered(reason)
+            }
+            Err(err) => {
+                *pending = Some(reason);
+                self.failed.store(true, Ordering::Relaxed);
+                SupervisorEvent::RecoveryFailed(reason, format!("{err:?}"))
+            }
+        })
+    }
+}
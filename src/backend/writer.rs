@@ -0,0 +1,199 @@
+use super::{null::Pacer, BackendSetup, BackendState, StateSlot};
+use crate::{wav, xrun::XrunKind, Backend, PcmFormat};
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pacing {
+    /// Produce audio at the rate a device would play it.
+    RealTime,
+    /// Produce audio as fast as the writer accepts it.
+    Unpaced,
+}
+
+#[derive(Debug, Clone)]
+pub struct WriterSettings {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub buffer_size: u32,
+    pub format: PcmFormat,
+    /// Start the output with a WAV header. Since the length isn't known in advance, the header
+    /// declares the maximum size.
+    pub wav_header: bool,
+    pub pacing: Pacing,
+}
+impl Default for WriterSettings {
+    fn default() -> Self {
+        Self {
+            sample_rate: 48000,
+            channels: 2,
+            buffer_size: 1024,
+            format: PcmFormat::F32,
+            wav_header: false,
+            pacing: Pacing::RealTime,
+        }
+    }
+}
+
+type Writer = Box<dyn Write + Send>;
+
+/// A backend that streams interleaved PCM to a [`Write`], e.g. the stdin of an encoder or a
+/// file. Rendering happens on a dedicated thread, which owns the writer while running.
+///
+/// A failed write stops the thread and marks the backend as broken.
+pub struct WriterBackend {
+    settings: WriterSettings,
+    writer: Option<Writer>,
+    header_written: bool,
+    state: Option<Arc<StateSlot>>,
+    thread: Option<(Arc<AtomicBool>, JoinHandle<Writer>)>,
+    suspended: bool,
+    broken: Arc<AtomicBool>,
+}
+
+impl WriterBackend {
+    pub fn new(writer: impl Write + Send + 'static, settings: WriterSettings) -> Self {
+        Self {
+            settings,
+            writer: Some(Box::new(writer)),
+            header_written: false,
+            state: None,
+            thread: None,
+            suspended: false,
+            broken: Arc::default(),
+        }
+    }
+
+    fn stop_thread(&mut self) {
+        if let Some((stop, handle)) = self.thread.take() {
+            stop.store(true, Ordering::Relaxed);
+            if let Ok(writer) = handle.join() {
+                self.writer = Some(writer);
+            }
+        }
+    }
+}
+
+impl Backend for WriterBackend {
+    fn setup(&mut self, setup: BackendSetup) -> Result<()> {
+        self.state = Some(Arc::new(setup.into()));
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        self.stop_thread();
+        self.suspended = false;
+
+        let WriterSettings {
+            sample_rate,
+            channels,
+            buffer_size,
+            format,
+            wav_header,
+            pacing,
+        } = self.settings.clone();
+        let writer = self
+            .writer
+            .as_mut()
+            .ok_or_else(|| anyhow!("writer is lost"))?;
+        if wav_header && !self.header_written {
+            wav::write_header(writer, sample_rate, channels, format, None)
+                .context("failed to write wav header")?;
+            self.header_written = true;
+        }
+        let slot = self.state.as_ref().context("backend is not set up")?;
+        let stats = Arc::clone(&slot.stats);
+        let mut state = slot.take()?;
+        let mut writer = self.writer.take().context("writer is lost")?;
+        state.set_sample_rate(sample_rate);
+        state.0.prepare(buffer_size as usize);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let broken = Arc::clone(&self.broken);
+        let handle = thread::Builder::new()
+            .name("sasa-writer".to_owned())
+            .spawn({
+                let stop = Arc::clone(&stop);
+                move || {
+                    let mut pacer = Pacer::new(buffer_size, sample_rate);
+                    let mut data = vec![0.; buffer_size as usize * channels as usize];
+                    let mut bytes = Vec::with_capacity(data.len() * format.bytes_per_sample());
+                    while !stop.load(Ordering::Relaxed) {
+                        // faster than real time, the timestamps would only mislead the clock
+                        let timestamp = (pacing == Pacing::RealTime).then(|| pacer.timestamp());
+                        state.0.render_timed(channels, &mut data, timestamp);
+                        bytes.clear();
+                        format.encode(&data, &mut bytes);
+                        if let Err(err) = writer.write_all(&bytes) {
+                            stats.xruns.push(XrunKind::StreamError(format!(
+                                "failed to write audio: {err}"
+                            )));
+                            broken.store(true, Ordering::Relaxed);
+                            break;
+                        }
+                        if pacing == Pacing::RealTime {
+                            pacer.wait();
+                        }
+                    }
+                    let _ = writer.flush();
+                    writer
+                }
+            })
+            .context("failed to spawn render thread")?;
+        self.thread = Some((stop, handle));
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.stop_thread();
+        self.suspended = false;
+        Ok(())
+    }
+
+    fn suspend(&mut self) -> Result<()> {
+        if self.thread.is_none() {
+            bail!("stream is not started");
+        }
+        self.stop_thread();
+        self.suspended = true;
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        if !self.suspended {
+            bail!("stream is not suspended");
+        }
+        self.start()
+    }
+
+    fn state(&self) -> BackendState {
+        if self
+            .thread
+            .as_ref()
+            .is_some_and(|(_, it)| !it.is_finished())
+        {
+            BackendState::Running
+        } else if self.suspended {
+            BackendState::Suspended
+        } else {
+            BackendState::Stopped
+        }
+    }
+
+    fn consume_broken(&self) -> bool {
+        self.broken.fetch_and(false, Ordering::Relaxed)
+    }
+}
+
+impl Drop for WriterBackend {
+    fn drop(&mut self) {
+        self.stop_thread();
+    }
+}
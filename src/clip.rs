@@ -1,24 +1,305 @@
-use crate::Frame;
-use anyhow::{anyhow, bail, Result};
-use std::{io::Cursor, sync::Arc};
+use crate::{
+    analysis, flac, probe, resample, wav, Frame, Interpolation, Loudness, MediaInfo, PcmFormat,
+    ResampleQuality,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    fs::File,
+    io::{Cursor, ErrorKind, Write},
+    path::Path,
+    sync::{Arc, OnceLock},
+};
 use symphonia::core::{
     audio::{AudioBufferRef, Signal},
-    io::MediaSourceStream,
+    codecs::CODEC_TYPE_NULL,
+    errors::Error,
+    formats::{FormatReader, Packet, Track},
+    io::{MediaSource, MediaSourceStream},
+    probe::{Hint, ProbeResult},
+    units::TimeBase,
 };
 
+/// Tells the prober what kind of file to expect. Without a hint every supported format is tried.
+#[derive(Debug, Clone, Default)]
+pub struct FormatHint {
+    /// File extension without the dot, e.g. `"ogg"`.
+    pub extension: Option<String>,
+    /// MIME type, e.g. `"audio/ogg"`.
+    pub mime_type: Option<String>,
+}
+
+impl FormatHint {
+    /// A hint from the extension of `path`, if it has one.
+    pub fn from_path(path: &Path) -> Self {
+        Self {
+            extension: path
+                .extension()
+                .and_then(|it| it.to_str())
+                .map(str::to_owned),
+            mime_type: None,
+        }
+    }
+}
+
+/// An audio track of a media file, as listed by [`AudioClip::tracks`].
+#[derive(Debug, Clone)]
+pub struct TrackInfo {
+    pub id: u32,
+    /// Short name of the codec, or `None` if it is not supported.
+    pub codec: Option<&'static str>,
+    pub language: Option<String>,
+    pub channels: Option<u16>,
+    pub sample_rate: Option<u32>,
+    /// Duration in seconds, if the container tells it.
+    pub duration: Option<f32>,
+    pub is_default: bool,
+}
+
+/// Which track of a media file is decoded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TrackSelector {
+    #[default]
+    Default,
+    /// The track with this [`TrackInfo::id`].
+    Id(u32),
+    /// The first track in this language (e.g. `"eng"`), compared case-insensitively.
+    Language(String),
+}
+
+/// What decoding does when the input is damaged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DecodePolicy {
+    /// Fail on the first packet that can't be decoded or read, except for the first frames of
+    /// an mp3 that refer to data before the start of the stream, which are skipped and reported.
+    #[default]
+    Strict,
+    /// Skip packets that can't be decoded, but fail if the stream can't be read.
+    SkipCorrupt,
+    /// Skip packets that can't be decoded and keep what was decoded when the stream can't be
+    /// read any further.
+    BestEffort,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+    pub hint: FormatHint,
+    pub track: TrackSelector,
+    pub policy: DecodePolicy,
+}
+
+/// Why decoding stopped.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum StreamEnd {
+    #[default]
+    Finished,
+    /// The stream changed its parameters midway (e.g. chained Ogg streams), which decoding does
+    /// not follow.
+    Reset,
+    /// The stream could not be read any further.
+    ReadError(String),
+    /// The stream ended without errors, but before the number of frames the container
+    /// announced. Some formats (e.g. MP3 without a Xing header) only estimate it, so this is not
+    /// an error under any policy.
+    Truncated { expected: u64, decoded: u64 },
+}
+
+#[derive(Debug, Clone)]
+pub struct SkippedPacket {
+    /// Position of the packet in seconds.
+    pub time: f32,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DecodeReport {
+    /// Number of packets decoded.
+    pub packets: u64,
+    /// Number of packets skipped because they could not be decoded.
+    pub skipped: u64,
+    /// The first skipped packets, up to [`DecodeReport::MAX_SKIPPED_PACKETS`].
+    pub skipped_packets: Vec<SkippedPacket>,
+    pub end: StreamEnd,
+}
+
+impl DecodeReport {
+    pub const MAX_SKIPPED_PACKETS: usize = 64;
+
+    fn skip(&mut self, ts: u64, time_base: Option<TimeBase>, sample_rate: u32, reason: String) {
+        self.skipped += 1;
+        if self.skipped_packets.len() < Self::MAX_SKIPPED_PACKETS {
+            let time = match time_base {
+                Some(time_base) => {
+                    let time = time_base.calc_time(ts);
+                    time.seconds as f64 + time.frac
+                }
+                None => ts as f64 / sample_rate as f64,
+            };
+            self.skipped_packets.push(SkippedPacket {
+                time: time as f32,
+                reason,
+            });
+        }
+    }
+}
+
+pub(crate) fn probe_source(
+    source: Box<dyn MediaSource>,
+    hint: &FormatHint,
+) -> Result<ProbeResult> {
+    let mut symphonia_hint = Hint::new();
+    if let Some(extension) = &hint.extension {
+        symphonia_hint.with_extension(extension);
+    }
+    if let Some(mime_type) = &hint.mime_type {
+        symphonia_hint.mime_type(mime_type);
+    }
+    let mss = MediaSourceStream::new(source, Default::default());
+    Ok(symphonia::default::get_probe().format(
+        &symphonia_hint,
+        mss,
+        &Default::default(),
+        &Default::default(),
+    )?)
+}
+
+/// Whether `err` comes from an mp3 frame whose bit reservoir points before the start of the
+/// stream, as the first frames of many files do. Such frames are skipped under every policy.
+pub(crate) fn is_missing_reservoir(err: &Error) -> bool {
+    matches!(err, Error::DecodeError(reason) if reason.contains("invalid main_data offset"))
+}
+
+/// Opens the file at `path` with a hint from its extension.
+fn open_file(path: &Path) -> Result<(File, FormatHint)> {
+    let file = File::open(path).with_context(|| format!("cannot open `{}`", path.display()))?;
+    Ok((file, FormatHint::from_path(path)))
+}
+
+pub(crate) fn track_info(track: &Track, default_id: Option<u32>) -> TrackInfo {
+    let params = &track.codec_params;
+    TrackInfo {
+        id: track.id,
+        codec: symphonia::default::get_codecs()
+            .get_codec(params.codec)
+            .map(|it| it.short_name),
+        language: track.language.clone(),
+        channels: params.channels.map(|it| it.count() as u16),
+        sample_rate: params.sample_rate,
+        duration: params
+            .n_frames
+            .zip(params.sample_rate)
+            .map(|(frames, rate)| frames as f32 / rate as f32),
+        is_default: Some(track.id) == default_id,
+    }
+}
+
+pub(crate) fn select_track<'a>(
+    format_reader: &'a dyn FormatReader,
+    selector: &TrackSelector,
+) -> Result<&'a Track> {
+    let mut tracks = format_reader
+        .tracks()
+        .iter()
+        .filter(|it| it.codec_params.codec != CODEC_TYPE_NULL);
+    match selector {
+        TrackSelector::Default => format_reader
+            .default_track()
+            .ok_or_else(|| anyhow!("default track not found")),
+        TrackSelector::Id(id) => tracks
+            .find(|it| it.id == *id)
+            .ok_or_else(|| anyhow!("track {id} not found")),
+        TrackSelector::Language(language) => tracks
+            .find(|it| {
+                it.language
+                    .as_ref()
+                    .is_some_and(|it| it.eq_ignore_ascii_case(language))
+            })
+            .ok_or_else(|| anyhow!("track in language `{language}` not found")),
+    }
+}
+
+#[inline(always)]
+fn load_frames_from_buffer(
+    frames: &mut Vec<Frame>,
+    buffer: &symphonia::core::audio::AudioBuffer<f32>,
+) {
+    match buffer.spec().channels.count() {
+        1 => {
+            let chan = buffer.chan(0);
+            frames.extend(chan.iter().map(|&sample| Frame(sample, sample)));
+        }
+        _ => {
+            let left = buffer.chan(0);
+            let right = buffer.chan(1);
+            frames.extend(
+                left.iter()
+                    .zip(right.iter())
+                    .map(|(&left, &right)| Frame(left, right)),
+            );
+        }
+    }
+}
+
+#[inline(always)]
+pub(crate) fn load_frames_from_buffer_ref(
+    frames: &mut Vec<Frame>,
+    buffer: &AudioBufferRef,
+) -> Result<()> {
+    macro_rules! conv {
+        ($buffer:ident) => {{
+            let mut dest = symphonia::core::audio::AudioBuffer::new(
+                buffer.capacity() as u64,
+                buffer.spec().clone(),
+            );
+            $buffer.convert(&mut dest);
+            load_frames_from_buffer(frames, &dest);
+        }};
+    }
+    use AudioBufferRef::*;
+    match buffer {
+        F32(buffer) => load_frames_from_buffer(frames, buffer),
+        U8(buffer) => conv!(buffer),
+        U16(buffer) => conv!(buffer),
+        U24(buffer) => conv!(buffer),
+        U32(buffer) => conv!(buffer),
+        S8(buffer) => conv!(buffer),
+        S16(buffer) => conv!(buffer),
+        S24(buffer) => conv!(buffer),
+        S32(buffer) => conv!(buffer),
+        F64(buffer) => conv!(buffer),
+    }
+    Ok(())
+}
+
 #[repr(align(32))]
 struct ClipInner {
     frames: Vec<Frame>,
     sample_rate: u32,
-    length: f32,
-    frame_count: usize,
 }
 
-pub struct AudioClip(Arc<ClipInner>);
+/// Decoded audio, shared between clones. A clip may be a [slice](AudioClip::slice) of the frames
+/// it shares.
+pub struct AudioClip {
+    inner: Arc<ClipInner>,
+    interpolation: Interpolation,
+    gain: f32,
+    offset: usize,
+    frame_count: usize,
+    length: f32,
+    /// Integrated loudness of the viewed frames, measured on first use.
+    integrated: Arc<OnceLock<Option<f32>>>,
+}
 
 impl Clone for AudioClip {
     fn clone(&self) -> Self {
-        Self(Arc::clone(&self.0))
+        Self {
+            inner: Arc::clone(&self.inner),
+            interpolation: self.interpolation,
+            gain: self.gain,
+            offset: self.offset,
+            frame_count: self.frame_count,
+            length: self.length,
+            integrated: Arc::clone(&self.integrated),
+        }
     }
 }
 
@@ -26,100 +307,121 @@ impl AudioClip {
     pub fn from_raw(frames: Vec<Frame>, sample_rate: u32) -> Self {
         let frame_count = frames.len();
         let length = frame_count as f32 / sample_rate as f32;
-        Self(Arc::new(ClipInner {
-            frames,
-            sample_rate,
-            length,
+        Self {
+            inner: Arc::new(ClipInner {
+                frames,
+                sample_rate,
+            }),
+            interpolation: Interpolation::default(),
+            gain: 1.,
+            offset: 0,
             frame_count,
-        }))
+            length,
+            integrated: Arc::default(),
+        }
+    }
+
+    /// Lists the audio tracks of a media file without decoding them.
+    pub fn tracks(data: Vec<u8>) -> Result<Vec<TrackInfo>> {
+        Self::tracks_source(Cursor::new(data), &FormatHint::default())
+    }
+
+    /// Like [`AudioClip::tracks`], for the file at `path`.
+    pub fn tracks_path(path: impl AsRef<Path>) -> Result<Vec<TrackInfo>> {
+        let (file, hint) = open_file(path.as_ref())?;
+        Self::tracks_source(file, &hint)
+    }
+
+    /// Like [`AudioClip::tracks`], for `source`.
+    pub fn tracks_source(
+        source: impl MediaSource + 'static,
+        hint: &FormatHint,
+    ) -> Result<Vec<TrackInfo>> {
+        let format_reader = probe_source(Box::new(source), hint)?.format;
+        let default_id = format_reader.default_track().map(|it| it.id);
+        Ok(format_reader
+            .tracks()
+            .iter()
+            .filter(|it| it.codec_params.codec != CODEC_TYPE_NULL)
+            .map(|track| track_info(track, default_id))
+            .collect())
+    }
+
+    /// Reads the tags, pictures and stream parameters of a media file. Audio packets are only
+    /// read, not decoded, and only if the container doesn't tell the duration.
+    pub fn probe(data: Vec<u8>) -> Result<MediaInfo> {
+        Self::probe_source(Cursor::new(data), &FormatHint::default())
+    }
+
+    /// Like [`AudioClip::probe`], for the file at `path`. Only the parts of the file needed are
+    /// read.
+    pub fn probe_path(path: impl AsRef<Path>) -> Result<MediaInfo> {
+        let (file, hint) = open_file(path.as_ref())?;
+        Self::probe_source(file, &hint)
+    }
+
+    /// Like [`AudioClip::probe`], for `source`.
+    pub fn probe_source(
+        source: impl MediaSource + 'static,
+        hint: &FormatHint,
+    ) -> Result<MediaInfo> {
+        probe::probe(probe_source(Box::new(source), hint)?)
     }
 
     pub fn decode(data: Vec<u8>) -> Result<(Vec<Frame>, u32)> {
-        const CHUNK_SIZE: usize = 4096;
+        Self::decode_track(data, &TrackSelector::Default)
+    }
 
-        #[inline(always)]
-        fn load_frames_from_buffer(
-            frames: &mut Vec<Frame>,
-            buffer: &symphonia::core::audio::AudioBuffer<f32>,
-        ) {
-            match buffer.spec().channels.count() {
-                1 => {
-                    let chan = buffer.chan(0);
-                    let current_len = frames.len();
-                    frames.reserve(chan.len());
-                    unsafe {
-                        frames.set_len(current_len + chan.len());
-                    }
-                    for (i, &sample) in chan.iter().enumerate() {
-                        frames[current_len + i] = Frame(sample, sample);
-                    }
-                }
-                _ => {
-                    let left = buffer.chan(0);
-                    let right = buffer.chan(1);
-                    let current_len = frames.len();
-                    frames.reserve(left.len());
-                    unsafe {
-                        frames.set_len(current_len + left.len());
-                    }
-                    for i in 0..left.len() {
-                        frames[current_len + i] = Frame(left[i], right[i]);
-                    }
-                }
-            }
-        }
+    /// Like [`AudioClip::decode`], but decodes the track chosen by `track`.
+    pub fn decode_track(data: Vec<u8>, track: &TrackSelector) -> Result<(Vec<Frame>, u32)> {
+        Self::decode_source(Cursor::new(data), &FormatHint::default(), track)
+    }
 
-        #[inline(always)]
-        fn load_frames_from_buffer_ref(
-            frames: &mut Vec<Frame>,
-            buffer: &AudioBufferRef,
-        ) -> Result<()> {
-            macro_rules! conv {
-                ($buffer:ident) => {{
-                    let mut dest = symphonia::core::audio::AudioBuffer::new(
-                        buffer.capacity() as u64,
-                        buffer.spec().clone(),
-                    );
-                    $buffer.convert(&mut dest);
-                    load_frames_from_buffer(frames, &dest);
-                }};
-            }
-            use AudioBufferRef::*;
-            match buffer {
-                F32(buffer) => load_frames_from_buffer(frames, buffer),
-                U8(buffer) => conv!(buffer),
-                U16(buffer) => conv!(buffer),
-                U24(buffer) => conv!(buffer),
-                U32(buffer) => conv!(buffer),
-                S8(buffer) => conv!(buffer),
-                S16(buffer) => conv!(buffer),
-                S24(buffer) => conv!(buffer),
-                S32(buffer) => conv!(buffer),
-                F64(buffer) => conv!(buffer),
-            }
-            Ok(())
-        }
+    /// Decodes the track chosen by `track` from `source`, e.g. a [`File`], reading it as it
+    /// goes instead of loading it into memory first.
+    pub fn decode_source(
+        source: impl MediaSource + 'static,
+        hint: &FormatHint,
+        track: &TrackSelector,
+    ) -> Result<(Vec<Frame>, u32)> {
+        let options = DecodeOptions {
+            hint: hint.clone(),
+            track: track.clone(),
+            ..DecodeOptions::default()
+        };
+        let (frames, sample_rate, _) = Self::decode_with(source, &options)?;
+        Ok((frames, sample_rate))
+    }
+
+    /// Decodes `source` as configured by `options`, reporting what went wrong along the way.
+    pub fn decode_with(
+        source: impl MediaSource + 'static,
+        options: &DecodeOptions,
+    ) -> Result<(Vec<Frame>, u32, DecodeReport)> {
+        Self::decode_with_progress(Box::new(source), options, &mut |_| true)
+    }
+
+    /// Decodes like [`AudioClip::decode_with`], calling `progress` with the size in bytes of
+    /// every packet. Decoding stops with an error as soon as `progress` returns `false`.
+    pub(crate) fn decode_with_progress(
+        source: Box<dyn MediaSource>,
+        options: &DecodeOptions,
+        progress: &mut dyn FnMut(usize) -> bool,
+    ) -> Result<(Vec<Frame>, u32, DecodeReport)> {
+        const CHUNK_SIZE: usize = 4096;
 
         let codecs = symphonia::default::get_codecs();
-        let probe = symphonia::default::get_probe();
-        let mss = MediaSourceStream::new(Box::new(Cursor::new(data)), Default::default());
-        let mut format_reader = probe
-            .format(
-                &Default::default(),
-                mss,
-                &Default::default(),
-                &Default::default(),
-            )?
-            .format;
-
-        let track = format_reader
-            .default_track()
-            .ok_or_else(|| anyhow!("default track not found"))?;
+        let mut format_reader = probe_source(source, &options.hint)?.format;
+
+        let track = select_track(format_reader.as_ref(), &options.track)?;
+        let track_id = track.id;
 
         let codec_params = &track.codec_params;
         let sample_rate = codec_params
             .sample_rate
             .ok_or_else(|| anyhow!("unknown sample rate"))?;
+        let time_base = codec_params.time_base;
+        let n_frames = codec_params.n_frames;
         
         /*
         magic????
@@ -135,41 +437,73 @@ impl AudioClip {
 
         let mut frames = Vec::new();
         let mut decoder = codecs.make(codec_params, &Default::default())?;
+        let policy = options.policy;
+        let mut report = DecodeReport::default();
+
+        let mut decode_packet = |packet: &Packet,
+                                 frames: &mut Vec<Frame>,
+                                 report: &mut DecodeReport|
+         -> Result<()> {
+            match decoder.decode(packet) {
+                Ok(buffer) => {
+                    load_frames_from_buffer_ref(frames, &buffer)?;
+                    report.packets += 1;
+                }
+                Err(err) if is_missing_reservoir(&err) => {
+                    report.skip(packet.ts, time_base, sample_rate, err.to_string());
+                }
+                Err(err @ (Error::DecodeError(_) | Error::IoError(_)))
+                    if policy != DecodePolicy::Strict =>
+                {
+                    report.skip(packet.ts, time_base, sample_rate, err.to_string());
+                }
+                Err(err) => return Err(err.into()),
+            }
+            if !progress(packet.data.len()) {
+                bail!("decoding was cancelled");
+            }
+            Ok(())
+        };
 
         // 块处理解码
         let mut packets = Vec::with_capacity(CHUNK_SIZE);
-        while let Ok(packet) = format_reader.next_packet() {
+        loop {
+            let packet = match format_reader.next_packet() {
+                Ok(packet) => packet,
+                Err(Error::IoError(err)) if err.kind() == ErrorKind::UnexpectedEof => break,
+                Err(Error::ResetRequired) if policy != DecodePolicy::Strict => {
+                    report.end = StreamEnd::Reset;
+                    break;
+                }
+                Err(err) if policy == DecodePolicy::BestEffort => {
+                    report.end = StreamEnd::ReadError(err.to_string());
+                    break;
+                }
+                Err(err) => return Err(err.into()),
+            };
+            if packet.track_id() != track_id {
+                continue;
+            }
             packets.push(packet);
             if packets.len() >= CHUNK_SIZE {
                 for packet in packets.drain(..) {
-                    let buffer = match decoder.decode(&packet) {
-                        Ok(buffer) => buffer,
-                        Err(symphonia::core::errors::Error::DecodeError(s))
-                        if s.contains("invalid main_data offset") =>
-                            {
-                                continue;
-                            }
-                        Err(err) => return Err(err.into()),
-                    };
-                    load_frames_from_buffer_ref(&mut frames, &buffer)?;
+                    decode_packet(&packet, &mut frames, &mut report)?;
                 }
             }
         }
         
         for packet in packets {
-            let buffer = match decoder.decode(&packet) {
-                Ok(buffer) => buffer,
-                Err(symphonia::core::errors::Error::DecodeError(s))
-                if s.contains("invalid main_data offset") =>
-                    {
-                        continue;
-                    }
-                Err(err) => return Err(err.into()),
-            };
-            load_frames_from_buffer_ref(&mut frames, &buffer)?;
+            decode_packet(&packet, &mut frames, &mut report)?;
         }
 
-        Ok((frames, sample_rate))
+        let decoded = frames.len() as u64;
+        match n_frames {
+            Some(expected) if report.end == StreamEnd::Finished && decoded < expected => {
+                report.end = StreamEnd::Truncated { expected, decoded };
+            }
+            _ => {}
+        }
+        Ok((frames, sample_rate, report))
     }
 
     #[inline]
@@ -177,47 +511,188 @@ impl AudioClip {
         let (frames, sample_rate) = Self::decode(data)?;
         Ok(Self::from_raw(frames, sample_rate))
     }
+
+    /// Decodes the file at `path`, guessing its format from the extension first.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let (file, hint) = open_file(path.as_ref())?;
+        Self::from_source(file, &hint)
+    }
+
+    /// Decodes the default track of `source`, e.g. a [`File`] or a [`Cursor`].
+    pub fn from_source(source: impl MediaSource + 'static, hint: &FormatHint) -> Result<Self> {
+        let (frames, sample_rate) = Self::decode_source(source, hint, &TrackSelector::Default)?;
+        Ok(Self::from_raw(frames, sample_rate))
+    }
+
+    /// Like [`AudioClip::new`], but decodes the track chosen by `track`.
+    pub fn with_track(data: Vec<u8>, track: &TrackSelector) -> Result<Self> {
+        let (frames, sample_rate) = Self::decode_track(data, track)?;
+        Ok(Self::from_raw(frames, sample_rate))
+    }
     
+    /// Returns a clip sharing the frames of this one that [samples](AudioClip::sample) with
+    /// `interpolation`.
+    pub fn with_interpolation(&self, interpolation: Interpolation) -> Self {
+        interpolation.prepare();
+        Self {
+            interpolation,
+            ..self.clone()
+        }
+    }
+
+    /// Returns a view of the part of this clip between `start` and `end` seconds, sharing its
+    /// frames. Times are clamped to the clip.
+    pub fn slice(&self, start: f32, end: f32) -> Self {
+        let to_frame = |time: f32| {
+            ((time.max(0.) as f64 * self.inner.sample_rate as f64).round() as usize)
+                .min(self.frame_count)
+        };
+        let start = to_frame(start);
+        let end = to_frame(end).max(start);
+        let frame_count = end - start;
+        Self {
+            offset: self.offset + start,
+            frame_count,
+            length: frame_count as f32 / self.inner.sample_rate as f32,
+            integrated: Arc::default(),
+            ..self.clone()
+        }
+    }
+
+    #[inline(always)]
+    pub fn interpolation(&self) -> Interpolation {
+        self.interpolation
+    }
+
+    /// Returns this clip played with `gain`, sharing its frames. Sampling, and so sfx and music
+    /// created from the clip, applies the gain; [`AudioClip::frames`] stays untouched.
+    pub fn with_gain(&self, gain: f32) -> Self {
+        Self {
+            gain,
+            ..self.clone()
+        }
+    }
+
+    #[inline(always)]
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    #[inline]
     pub fn sample(&self, position: f32) -> Option<Frame> {
-        let position = position * self.0.sample_rate as f32;
+        self.sample_with(position, self.interpolation)
+    }
+
+    /// Like [`AudioClip::sample`], but with `interpolation` instead of the clip's own.
+    pub fn sample_with(&self, position: f32, interpolation: Interpolation) -> Option<Frame> {
+        let position = position * self.inner.sample_rate as f32;
         let actual_index = position as usize;
         
-        if actual_index >= self.0.frame_count {
+        if actual_index >= self.frame_count {
             return None;
         }
 
-        let frame = self.0.frames[actual_index];
         let t = position - actual_index as f32;
         
         if t < f32::EPSILON {
-            return Some(frame);
-        }
-
-        if actual_index + 1 >= self.0.frame_count {
-            return Some(frame);
+            return Some(self.frames()[actual_index] * self.gain);
         }
 
-        let next_frame = self.0.frames[actual_index + 1];
-        Some(frame.interpolate(&next_frame, t))
+        Some(interpolation.interpolate(self.frames(), actual_index, t) * self.gain)
     }
 
     #[inline(always)]
     pub fn frames(&self) -> &[Frame] {
-        &self.0.frames
+        &self.inner.frames[self.offset..self.offset + self.frame_count]
     }
 
     #[inline(always)]
     pub fn sample_rate(&self) -> u32 {
-        self.0.sample_rate
+        self.inner.sample_rate
     }
 
     #[inline(always)]
     pub fn frame_count(&self) -> usize {
-        self.0.frame_count
+        self.frame_count
     }
 
     #[inline(always)]
     pub fn length(&self) -> f32 {
-        self.0.length
+        self.length
+    }
+
+    /// Returns this clip converted to `sample_rate`, which avoids converting it with linear
+    /// interpolation during playback.
+    pub fn resample(&self, sample_rate: u32, quality: ResampleQuality) -> Self {
+        if sample_rate == self.inner.sample_rate {
+            return self.clone();
+        }
+        Self::from_raw(
+            resample::resample(self.frames(), self.inner.sample_rate, sample_rate, quality),
+            sample_rate,
+        )
+        .with_interpolation(self.interpolation)
+        .with_gain(self.gain)
+    }
+
+    /// Measures the levels of the frames of this clip, regardless of its gain.
+    pub fn loudness(&self) -> Loudness {
+        analysis::loudness(self.frames(), self.integrated_loudness())
+    }
+
+    /// Returns [`Loudness::integrated`] without measuring the other levels. The result is kept
+    /// and shared with the clones of this clip, including those with another gain.
+    pub fn integrated_loudness(&self) -> Option<f32> {
+        *self
+            .integrated
+            .get_or_init(|| analysis::integrated(self.frames(), self.inner.sample_rate))
+    }
+
+    /// Returns the linear gain that brings the integrated loudness of this clip to `target`
+    /// LUFS, see [`AudioClip::integrated_loudness`].
+    pub fn gain_to(&self, target: f32) -> Option<f32> {
+        analysis::gain_to(self.integrated_loudness(), target)
+    }
+
+    /// Returns a copy of this clip with its frames scaled to an integrated loudness of `target`
+    /// LUFS. Clips too short or quiet to be measured are returned as they are.
+    ///
+    /// Gains above one may push the peaks past full scale; see [`Loudness::true_peak`].
+    pub fn normalize(&self, target: f32) -> Self {
+        let Some(gain) = self.gain_to(target) else {
+            return self.clone();
+        };
+        let frames = self.frames().iter().map(|it| *it * gain).collect();
+        Self::from_raw(frames, self.inner.sample_rate)
+            .with_interpolation(self.interpolation)
+            .with_gain(self.gain)
+    }
+
+    /// Writes this clip as a stereo WAV file.
+    pub fn write_wav(&self, mut w: impl Write, format: PcmFormat) -> Result<()> {
+        let data_len = self.frame_count as u64 * 2 * format.bytes_per_sample() as u64;
+        let data_len = u32::try_from(data_len).context("clip is too long for wav")?;
+        wav::write_header(&mut w, self.inner.sample_rate, 2, format, Some(data_len))?;
+        let mut bytes = Vec::new();
+        for chunk in self.frames().chunks(4096) {
+            let samples: Vec<f32> = chunk.iter().flat_map(|it| [it.0, it.1]).collect();
+            bytes.clear();
+            format.encode(&samples, &mut bytes);
+            w.write_all(&bytes)?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Writes this clip as a stereo FLAC file. FLAC only stores integers, so `format` is
+    /// either [`PcmFormat::I16`] or [`PcmFormat::I24`]. Fails on empty clips, which FLAC
+    /// readers don't accept.
+    pub fn write_flac(&self, mut w: impl Write, format: PcmFormat) -> Result<()> {
+        let bits = match format {
+            PcmFormat::I16 => 16,
+            PcmFormat::I24 => 24,
+            PcmFormat::F32 => bail!("flac does not support float samples"),
+        };
+        flac::encode(&mut w, self.frames(), self.inner.sample_rate, bits)
     }
 }
\ No newline at end of file
@@ -0,0 +1,295 @@
+use std::{
+    sync::{
+        atomic::{fence, AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+/// Weight of each new callback in the estimated stream start time. Lower values smooth out
+/// callback jitter better but follow drift more slowly.
+const SMOOTHING: f64 = 0.05;
+/// Estimation errors beyond this many seconds are treated as a discontinuity (e.g. a restarted
+/// or resumed stream) and applied immediately instead of being smoothed.
+const RESYNC_THRESHOLD: f64 = 0.05;
+
+/// A run of output at a single sample rate, starting after `frames` frames or `seconds` seconds
+/// of output.
+#[derive(Clone, Copy, Default)]
+struct Segment {
+    frames: u64,
+    seconds: f64,
+    sample_rate: u32,
+}
+
+/// What a single callback wrote, see [`ClockState::read`].
+struct Snapshot {
+    frames: u64,
+    rendered: f64,
+    start: f64,
+    segment: Segment,
+    /// The segment before the latest sample rate change.
+    previous: Segment,
+}
+
+impl Snapshot {
+    /// Index of the frame at `seconds` of output.
+    fn frames_at(&self, seconds: f64) -> u64 {
+        // the output heard may still be from before the latest rate change
+        let segment = if seconds < self.segment.seconds {
+            self.previous
+        } else {
+            self.segment
+        };
+        let frames = segment.frames
+            + ((seconds - segment.seconds).max(0.) * segment.sample_rate as f64) as u64;
+        frames.min(self.frames)
+    }
+}
+
+struct ClockState {
+    origin: Instant,
+    seq: AtomicU64,
+    frames: AtomicU64,
+    /// Seconds of audio rendered so far, as f64 bits.
+    rendered: AtomicU64,
+    /// Estimated time since `origin` at which rendering started, as f64 bits.
+    start: AtomicU64,
+    sample_rate: AtomicU32,
+    /// Frames and seconds (as f64 bits) at which the current and previous segments start, and
+    /// the sample rate of the previous one.
+    segment: [AtomicU64; 2],
+    previous: [AtomicU64; 2],
+    previous_rate: AtomicU32,
+    /// The largest value returned by [`AudioClock::now_seconds`], as f64 bits.
+    last: AtomicU64,
+}
+
+impl ClockState {
+    fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+            seq: AtomicU64::new(0),
+            frames: AtomicU64::new(0),
+            rendered: AtomicU64::new(0),
+            start: AtomicU64::new(0),
+            sample_rate: AtomicU32::new(0),
+            segment: Default::default(),
+            previous: Default::default(),
+            previous_rate: AtomicU32::new(0),
+            last: AtomicU64::new(0),
+        }
+    }
+
+    fn read(&self) -> Snapshot {
+        let segment = |[frames, seconds]: &[AtomicU64; 2], sample_rate: &AtomicU32| Segment {
+            frames: frames.load(Ordering::Relaxed),
+            seconds: f64::from_bits(seconds.load(Ordering::Relaxed)),
+            sample_rate: sample_rate.load(Ordering::Relaxed),
+        };
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 != 0 {
+                std::hint::spin_loop();
+                continue;
+            }
+            let result = Snapshot {
+                frames: self.frames.load(Ordering::Relaxed),
+                rendered: f64::from_bits(self.rendered.load(Ordering::Relaxed)),
+                start: f64::from_bits(self.start.load(Ordering::Relaxed)),
+                segment: segment(&self.segment, &self.sample_rate),
+                previous: segment(&self.previous, &self.previous_rate),
+            };
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == seq {
+                return result;
+            }
+        }
+    }
+}
+
+/// Audio thread side of [`AudioClock`], fed by the mixer on every callback.
+pub(crate) struct ClockRecorder {
+    state: Arc<ClockState>,
+    frames: u64,
+    rendered: f64,
+    start: Option<f64>,
+    /// What has to be added to the backend's timestamps to get the time since `origin`.
+    offset: Option<f64>,
+    segment: Segment,
+    previous: Segment,
+}
+
+impl ClockRecorder {
+    /// Records a callback that is about to render `frames` frames.
+    ///
+    /// `timestamp` is when the backend invoked the callback, in seconds on a clock of its own.
+    /// It is more precise than the time the mixer gets to run, which is used when the backend
+    /// doesn't tell.
+    pub fn push(&mut self, frames: usize, sample_rate: u32, timestamp: Option<f64>) {
+        let elapsed = self.state.origin.elapsed().as_secs_f64();
+        let now = match timestamp {
+            Some(timestamp) => {
+                // callbacks are never early, so the smallest offset is the most accurate one
+                let offset = match self.offset {
+                    Some(prev) if (elapsed - timestamp - prev).abs() < RESYNC_THRESHOLD => {
+                        prev.min(elapsed - timestamp)
+                    }
+                    _ => elapsed - timestamp,
+                };
+                self.offset = Some(offset);
+                timestamp + offset
+            }
+            None => elapsed,
+        };
+        let start = now - self.rendered;
+        let start = match self.start {
+            Some(prev) if (start - prev).abs() < RESYNC_THRESHOLD => {
+                prev + (start - prev) * SMOOTHING
+            }
+            _ => start,
+        };
+        self.start = Some(start);
+        if sample_rate != self.segment.sample_rate {
+            self.previous = self.segment;
+            self.segment = Segment {
+                frames: self.frames,
+                seconds: self.rendered,
+                sample_rate,
+            };
+        }
+        self.frames += frames as u64;
+        self.rendered += frames as f64 / sample_rate.max(1) as f64;
+
+        let state = &self.state;
+        let seq = state.seq.load(Ordering::Relaxed);
+        state.seq.store(seq + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        state.frames.store(self.frames, Ordering::Relaxed);
+        state
+            .rendered
+            .store(self.rendered.to_bits(), Ordering::Relaxed);
+        state.start.store(start.to_bits(), Ordering::Relaxed);
+        state.sample_rate.store(sample_rate, Ordering::Relaxed);
+        let segments = [
+            (&state.segment, self.segment),
+            (&state.previous, self.previous),
+        ];
+        for ([frames, seconds], segment) in segments {
+            frames.store(segment.frames, Ordering::Relaxed);
+            seconds.store(segment.seconds.to_bits(), Ordering::Relaxed);
+        }
+        state
+            .previous_rate
+            .store(self.previous.sample_rate, Ordering::Relaxed);
+        state.seq.store(seq + 2, Ordering::Release);
+    }
+}
+
+/// Tells which part of the output is being heard, based on the frames rendered by the mixer,
+/// the times of the callbacks and the measured output latency.
+///
+/// Between callbacks the position is extrapolated from the wall clock, so it advances smoothly
+/// instead of in buffer-sized steps.
+#[derive(Clone)]
+pub struct AudioClock {
+    state: Arc<ClockState>,
+    latency: Arc<AtomicU32>,
+}
+
+impl AudioClock {
+    pub(crate) fn new(latency: Arc<AtomicU32>) -> (Self, ClockRecorder) {
+        let state = Arc::new(ClockState::new());
+        (
+            Self {
+                state: Arc::clone(&state),
+                latency,
+            },
+            ClockRecorder {
+                state,
+                frames: 0,
+                rendered: 0.,
+                start: None,
+                offset: None,
+                segment: Segment::default(),
+                previous: Segment::default(),
+            },
+        )
+    }
+
+    /// Total number of frames rendered by the mixer, including the ones not yet heard.
+    pub fn frames_rendered(&self) -> u64 {
+        self.state.frames.load(Ordering::Relaxed)
+    }
+
+    /// Sample rate of the latest callback, or 0 if nothing was rendered yet.
+    pub fn sample_rate(&self) -> u32 {
+        self.state.sample_rate.load(Ordering::Relaxed)
+    }
+
+    /// Seconds of output heard at `instant`. The result never exceeds what has been rendered.
+    pub fn seconds_at(&self, instant: Instant) -> f64 {
+        self.seconds_in(&self.state.read(), instant)
+    }
+
+    fn seconds_in(&self, snapshot: &Snapshot, instant: Instant) -> f64 {
+        if snapshot.frames == 0 {
+            return 0.;
+        }
+        let latency = f32::from_bits(self.latency.load(Ordering::Relaxed)) as f64;
+        let time = instant
+            .checked_duration_since(self.state.origin)
+            .map_or(0., |it| it.as_secs_f64());
+        (time - snapshot.start - latency).clamp(0., snapshot.rendered)
+    }
+
+    /// Seconds of output heard now. Successive calls never go backwards.
+    pub fn now_seconds(&self) -> f64 {
+        self.now_seconds_in(&self.state.read())
+    }
+
+    fn now_seconds_in(&self, snapshot: &Snapshot) -> f64 {
+        let seconds = self.seconds_in(snapshot, Instant::now());
+        let last = self
+            .state
+            .last
+            .fetch_max(seconds.to_bits(), Ordering::Relaxed);
+        // non-negative floats order the same way as their bits
+        seconds.max(f64::from_bits(last))
+    }
+
+    /// Index of the output frame heard now. Frames are counted as they were rendered, so the
+    /// index stays right across sample rate changes.
+    pub fn now_frames(&self) -> u64 {
+        let snapshot = self.state.read();
+        snapshot.frames_at(self.now_seconds_in(&snapshot))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn late_callbacks_keep_their_timestamp() {
+        let (clock, mut rec) = AudioClock::new(Arc::default());
+        rec.push(480, 48000, Some(0.));
+        let start = clock.state.read().start;
+        // the mixer runs late, but the backend tells when the callback was due
+        thread::sleep(Duration::from_millis(20));
+        rec.push(480, 48000, Some(0.01));
+        assert!((clock.state.read().start - start).abs() < 1e-9);
+    }
+
+    #[test]
+    fn frames_across_rate_changes() {
+        let (clock, mut rec) = AudioClock::new(Arc::default());
+        rec.push(48000, 48000, Some(0.));
+        rec.push(44100, 44100, Some(1.));
+        let snapshot = clock.state.read();
+        assert_eq!(snapshot.frames_at(0.5), 24000);
+        assert_eq!(snapshot.frames_at(1.5), 48000 + 22050);
+        assert_eq!(snapshot.frames_at(3.), 48000 + 44100);
+    }
+}
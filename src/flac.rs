@@ -0,0 +1,392 @@
+//! A small FLAC encoder using fixed predictors and Rice coding, which gets most of the
+//! compression of the reference encoder at its fast presets.
+
+use crate::{wav::quantize, Frame};
+use anyhow::{bail, Result};
+use std::io::Write;
+
+const BLOCK_SIZE: usize = 4096;
+const MAX_FIXED_ORDER: usize = 4;
+const MAX_PARTITION_ORDER: u32 = 8;
+/// Rice parameters are 4 bits wide, with 15 reserved as the escape code.
+const MAX_RICE_PARAM: u32 = 14;
+
+struct BitWriter {
+    data: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            data: Vec::new(),
+            acc: 0,
+            bits: 0,
+        }
+    }
+
+    /// Writes the lowest `bits` bits of `value`, `bits` being at most 32.
+    fn write(&mut self, value: u64, bits: u32) {
+        // the accumulator may still hold 7 bits
+        debug_assert!(bits <= 32);
+        if bits == 0 {
+            return;
+        }
+        self.acc = (self.acc << bits) | (value & ((1 << bits) - 1));
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.data.push((self.acc >> self.bits) as u8);
+        }
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64, bits);
+    }
+
+    fn write_unary(&mut self, zeros: u64) {
+        let mut zeros = zeros;
+        while zeros >= 32 {
+            self.write(0, 32);
+            zeros -= 32;
+        }
+        self.write(1, zeros as u32 + 1);
+    }
+
+    fn align(&mut self) {
+        if self.bits != 0 {
+            self.write(0, 8 - self.bits);
+        }
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Frame numbers are coded like UTF-8 code points, extended to 31 bits.
+fn write_utf8(w: &mut BitWriter, value: u32) {
+    if value < 0x80 {
+        w.write(value as u64, 8);
+        return;
+    }
+    let mut len = 2;
+    while value >= 1 << (5 * len + 1) {
+        len += 1;
+    }
+    let first = (0xff00u32 >> len) as u8 | (value >> (6 * (len - 1))) as u8;
+    w.write(first as u64, 8);
+    for i in (0..len - 1).rev() {
+        w.write((0x80 | ((value >> (6 * i)) & 0x3f)) as u64, 8);
+    }
+}
+
+fn residuals(samples: &[i64], order: usize) -> Vec<i64> {
+    let s = samples;
+    (order..s.len())
+        .map(|i| match order {
+            0 => s[i],
+            1 => s[i] - s[i - 1],
+            2 => s[i] - 2 * s[i - 1] + s[i - 2],
+            3 => s[i] - 3 * s[i - 1] + 3 * s[i - 2] - s[i - 3],
+            _ => s[i] - 4 * s[i - 1] + 6 * s[i - 2] - 4 * s[i - 3] + s[i - 4],
+        })
+        .collect()
+}
+
+#[inline]
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Returns the cheapest Rice parameter for `residuals` and its cost in bits.
+fn rice_param(residuals: &[i64]) -> (u32, u64) {
+    let sum: u64 = residuals.iter().map(|it| zigzag(*it)).sum();
+    let n = residuals.len() as u64;
+    (0..=MAX_RICE_PARAM)
+        .map(|k| (k, n * (k as u64 + 1) + (sum >> k)))
+        .min_by_key(|(_, cost)| *cost)
+        .unwrap()
+}
+
+/// A residual split into `2^order` partitions, each with its own Rice parameter.
+struct Partitioning {
+    order: u32,
+    params: Vec<u32>,
+    bits: u64,
+}
+
+fn partition(residuals: &[i64], block_size: usize, predictor_order: usize) -> Partitioning {
+    let mut best: Option<Partitioning> = None;
+    for order in 0..=MAX_PARTITION_ORDER {
+        let len = block_size >> order;
+        if len << order != block_size || len < predictor_order {
+            break;
+        }
+        let mut params = Vec::with_capacity(1 << order);
+        let mut bits = 0;
+        let mut start = 0;
+        for i in 0..1 << order {
+            let end = start + if i == 0 { len - predictor_order } else { len };
+            let (param, cost) = rice_param(&residuals[start..end]);
+            params.push(param);
+            bits += 4 + cost;
+            start = end;
+        }
+        match &best {
+            Some(it) if it.bits <= bits => {}
+            _ => {
+                best = Some(Partitioning {
+                    order,
+                    params,
+                    bits,
+                })
+            }
+        }
+    }
+    best.unwrap()
+}
+
+fn write_subframe(w: &mut BitWriter, samples: &[i64], bits: u32) {
+    if samples.iter().all(|it| *it == samples[0]) {
+        w.write(0, 8);
+        w.write_signed(samples[0], bits);
+        return;
+    }
+
+    let verbatim_bits = samples.len() as u64 * bits as u64;
+    let best = (0..=MAX_FIXED_ORDER.min(samples.len() - 1))
+        .map(|order| {
+            let residuals = residuals(samples, order);
+            let partitioning = partition(&residuals, samples.len(), order);
+            let cost = order as u64 * bits as u64 + 6 + partitioning.bits;
+            (order, residuals, partitioning, cost)
+        })
+        .min_by_key(|(.., cost)| *cost)
+        .unwrap();
+    let (order, residuals, partitioning, cost) = best;
+
+    if cost >= verbatim_bits {
+        w.write(1 << 1, 8);
+        for sample in samples {
+            w.write_signed(*sample, bits);
+        }
+        return;
+    }
+
+    w.write((0b001000 | order as u64) << 1, 8);
+    for sample in &samples[..order] {
+        w.write_signed(*sample, bits);
+    }
+    w.write(0, 2);
+    w.write(partitioning.order as u64, 4);
+    let len = samples.len() >> partitioning.order;
+    let mut start = 0;
+    for (i, param) in partitioning.params.iter().enumerate() {
+        let end = start + if i == 0 { len - order } else { len };
+        w.write(*param as u64, 4);
+        for residual in &residuals[start..end] {
+            let value = zigzag(*residual);
+            w.write_unary(value >> param);
+            w.write(value, *param);
+        }
+        start = end;
+    }
+}
+
+fn write_frame(w: &mut BitWriter, number: u32, channels: [&[i64]; 2], bits: u32) {
+    let start = w.data.len();
+    let block_size = channels[0].len();
+    let sample_size = match bits {
+        16 => 0b100,
+        _ => 0b110,
+    };
+
+    let [left, right] = channels;
+    let side: Vec<_> = left.iter().zip(right).map(|(l, r)| l - r).collect();
+    let mid: Vec<_> = left.iter().zip(right).map(|(l, r)| (l + r) >> 1).collect();
+
+    // try every stereo decorrelation and keep the smallest
+    let encode = |a: &[i64], a_bits, b: &[i64], b_bits| {
+        let mut w = BitWriter::new();
+        write_subframe(&mut w, a, a_bits);
+        write_subframe(&mut w, b, b_bits);
+        w
+    };
+    let (assignment, body) = [
+        (0b0001, encode(left, bits, right, bits)),
+        (0b1000, encode(left, bits, &side, bits + 1)),
+        (0b1001, encode(&side, bits + 1, right, bits)),
+        (0b1010, encode(&mid, bits, &side, bits + 1)),
+    ]
+    .into_iter()
+    .min_by_key(|(_, it)| it.data.len() * 8 + it.bits as usize)
+    .unwrap();
+
+    w.write(0xfff8, 16);
+    // block size is stored after the header, the sample rate comes from the stream info
+    w.write(0b0111, 4);
+    w.write(0b0000, 4);
+    w.write(assignment, 4);
+    w.write(sample_size, 3);
+    w.write(0, 1);
+    write_utf8(w, number);
+    w.write(block_size as u64 - 1, 16);
+    let crc = crc8(&w.data[start..]);
+    w.write(crc as u64, 8);
+
+    // the header is a whole number of bytes
+    w.data.extend_from_slice(&body.data);
+    w.write(body.acc, body.bits);
+    w.align();
+    let crc = crc16(&w.data[start..]);
+    w.write(crc as u64, 16);
+}
+
+/// Encodes `frames` as a stereo FLAC stream of `bits` bits per sample, either 16 or 24.
+pub(crate) fn encode(
+    w: &mut impl Write,
+    frames: &[Frame],
+    sample_rate: u32,
+    bits: u32,
+) -> Result<()> {
+    if bits != 16 && bits != 24 {
+        bail!("unsupported bit depth for flac: {bits}");
+    }
+    if sample_rate == 0 || sample_rate >= 1 << 20 {
+        bail!("unsupported sample rate for flac: {sample_rate}");
+    }
+    // decoders look for the first frame right after the metadata
+    if frames.is_empty() {
+        bail!("flac cannot store an empty clip");
+    }
+
+    let mut header = BitWriter::new();
+    header.write(u32::from_be_bytes(*b"fLaC") as u64, 32);
+    // the stream info is the only metadata block
+    header.write(0x80, 8);
+    header.write(34, 24);
+    header.write(BLOCK_SIZE as u64, 16);
+    header.write(BLOCK_SIZE as u64, 16);
+    // frame sizes are unknown
+    header.write(0, 24);
+    header.write(0, 24);
+    header.write(sample_rate as u64, 20);
+    header.write(2 - 1, 3);
+    header.write(bits as u64 - 1, 5);
+    header.write(frames.len() as u64 >> 32, 4);
+    header.write(frames.len() as u64, 32);
+    // no MD5 signature
+    for _ in 0..4 {
+        header.write(0, 32);
+    }
+    w.write_all(&header.data)?;
+
+    let mut left = Vec::with_capacity(BLOCK_SIZE);
+    let mut right = Vec::with_capacity(BLOCK_SIZE);
+    let mut out = BitWriter::new();
+    for (number, block) in frames.chunks(BLOCK_SIZE).enumerate() {
+        left.clear();
+        right.clear();
+        for frame in block {
+            left.push(quantize(frame.0, bits) as i64);
+            right.push(quantize(frame.1, bits) as i64);
+        }
+        out.data.clear();
+        write_frame(&mut out, number as u32, [&left, &right], bits);
+        w.write_all(&out.data)?;
+    }
+    w.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AudioClip, Frame, PcmFormat};
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    /// A clip long enough for several blocks, with a shorter last one. It mixes a tone, noise
+    /// and a constant stretch so that every subframe type gets used.
+    fn clip() -> AudioClip {
+        let mut seed = 1u32;
+        let mut noise = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as f32 / u32::MAX as f32 * 2. - 1.
+        };
+        let frames = (0..10000)
+            .map(|i| match i {
+                0..=4095 => {
+                    let t = i as f32 / SAMPLE_RATE as f32;
+                    let tone = (t * 440. * std::f32::consts::TAU).sin();
+                    Frame(tone * 0.8, tone * 0.5 + noise() * 0.1)
+                }
+                4096..=8191 => Frame(noise(), -noise()),
+                _ => Frame(0.25, -1.),
+            })
+            .collect();
+        AudioClip::from_raw(frames, SAMPLE_RATE)
+    }
+
+    fn assert_round_trip(original: &AudioClip, data: Vec<u8>, bits: u32) {
+        // rounding, plus the decoder scaling by 2^(bits - 1) instead of 2^(bits - 1) - 1
+        let tolerance = 2. / (1 << (bits - 1)) as f32;
+        let decoded = AudioClip::new(data).unwrap();
+        assert_eq!(decoded.sample_rate(), SAMPLE_RATE);
+        assert_eq!(decoded.frame_count(), original.frame_count());
+        for (a, b) in original.frames().iter().zip(decoded.frames()) {
+            assert!(
+                (a.0 - b.0).abs() <= tolerance && (a.1 - b.1).abs() <= tolerance,
+                "{a:?} != {b:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let single = AudioClip::from_raw(vec![Frame(0.5, -0.5)], SAMPLE_RATE);
+        for original in [clip(), single] {
+            for (format, bits) in [(PcmFormat::I16, 16), (PcmFormat::I24, 24)] {
+                let mut data = Vec::new();
+                original.write_flac(&mut data, format).unwrap();
+                assert_round_trip(&original, data, bits);
+            }
+        }
+        assert!(clip().write_flac(Vec::new(), PcmFormat::F32).is_err());
+    }
+
+    #[test]
+    fn empty_clip() {
+        let clip = AudioClip::from_raw(Vec::new(), SAMPLE_RATE);
+        assert!(clip.write_flac(Vec::new(), PcmFormat::I16).is_err());
+    }
+}
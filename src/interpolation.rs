@@ -0,0 +1,85 @@
+use crate::{
+    resample::{kernel_at, kernel_table},
+    Frame,
+};
+use std::sync::OnceLock;
+
+const SINC_ZERO_CROSSINGS: usize = 4;
+const SINC_BETA: f64 = 6.;
+
+/// How [`AudioClip::sample`](crate::AudioClip::sample) computes audio between two frames. The
+/// difference is most audible when clips play slower than their sample rate, e.g. pitched down
+/// or on a device with a higher rate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Interpolation {
+    /// A straight line between the two nearest frames.
+    #[default]
+    Linear,
+    /// A Catmull-Rom spline through the four nearest frames.
+    CubicHermite,
+    /// A Kaiser-windowed sinc over the eight nearest frames.
+    Sinc,
+}
+
+fn sinc_table() -> &'static [f32] {
+    static TABLE: OnceLock<Vec<f32>> = OnceLock::new();
+    TABLE.get_or_init(|| kernel_table(SINC_ZERO_CROSSINGS, SINC_BETA))
+}
+
+impl Interpolation {
+    /// Builds the tables this mode needs, so that the audio thread never has to.
+    pub(crate) fn prepare(self) {
+        if self == Self::Sinc {
+            sinc_table();
+        }
+    }
+
+    /// Interpolates `frames` at `index + t`, where `index` is in range and `t` is in `[0, 1)`.
+    /// Frames outside `frames` are treated as silence, except by `Linear`, which holds the last
+    /// frame.
+    #[inline]
+    pub(crate) fn interpolate(self, frames: &[Frame], index: usize, t: f32) -> Frame {
+        match self {
+            Self::Linear => {
+                let next = frames.get(index + 1).unwrap_or(&frames[index]);
+                frames[index].interpolate(next, t)
+            }
+            Self::CubicHermite => {
+                let at = |offset: isize| {
+                    index
+                        .checked_add_signed(offset)
+                        .and_then(|it| frames.get(it))
+                        .copied()
+                        .unwrap_or_default()
+                };
+                let (p0, p1, p2, p3) = (at(-1), at(0), at(1), at(2));
+                let hermite = |p0: f32, p1: f32, p2: f32, p3: f32| {
+                    let a = 1.5 * (p1 - p2) + 0.5 * (p3 - p0);
+                    let b = p0 - 2.5 * p1 + 2. * p2 - 0.5 * p3;
+                    let c = 0.5 * (p2 - p0);
+                    ((a * t + b) * t + c) * t + p1
+                };
+                Frame(
+                    hermite(p0.0, p1.0, p2.0, p3.0),
+                    hermite(p0.1, p1.1, p2.1, p3.1),
+                )
+            }
+            Self::Sinc => {
+                let table = sinc_table();
+                let mut sum = Frame::default();
+                let mut weight_sum = 0.;
+                for offset in 1 - SINC_ZERO_CROSSINGS as isize..=SINC_ZERO_CROSSINGS as isize {
+                    let weight = kernel_at(table, t - offset as f32);
+                    weight_sum += weight;
+                    if let Some(frame) = index
+                        .checked_add_signed(offset)
+                        .and_then(|it| frames.get(it))
+                    {
+                        sum = sum + *frame * weight;
+                    }
+                }
+                sum * (1. / weight_sum)
+            }
+        }
+    }
+}
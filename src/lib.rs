@@ -1,18 +1,62 @@
 /// Simple And Stupid Audio for Rust, optimized for low latency.
 pub mod backend;
-pub use backend::Backend;
+pub use backend::{Backend, BackendState};
+
+mod analysis;
+pub use analysis::Loudness;
 
 mod clip;
-pub use clip::AudioClip;
+pub use clip::{
+    AudioClip, DecodeOptions, DecodePolicy, DecodeReport, FormatHint, SkippedPacket, StreamEnd,
+    TrackInfo, TrackSelector,
+};
+
+mod clock;
+pub use clock::AudioClock;
+
+mod flac;
+
+mod interpolation;
+pub use interpolation::Interpolation;
+
+mod loader;
+pub use loader::{ClipHandle, ClipLoader, LoadError, LoadProgress, LoadedClip, LoaderSettings};
 
 mod mixer;
+pub use mixer::{ChannelLayout, Upmix};
+
+mod offline;
+pub use offline::Timeline;
+
+mod resample;
+pub use resample::ResampleQuality;
+
+mod probe;
+pub use probe::{MediaInfo, Picture};
 
 mod renderer;
 pub use renderer::{Music, MusicParams, PlaySfxParams, Renderer, Sfx};
 
-use crate::{backend::BackendSetup, mixer::MixerCommand};
-use anyhow::{anyhow, Context, Result};
+mod stats;
+pub use stats::{AudioStats, INTERVAL_HISTOGRAM_BUCKETS, INTERVAL_HISTOGRAM_EDGES_MS};
+
+mod wav;
+pub use wav::PcmFormat;
+
+mod xrun;
+pub use xrun::{XrunEvent, XrunKind, XrunReport};
+
+use crate::{
+    backend::{
+        manual::{ManualBackend, ManualHandle, ManualSettings},
+        BackendSetup,
+    },
+    mixer::MixerCommand,
+    stats::{LatencyStatsRecorder, StatsRecorder, StatsState},
+};
+use anyhow::{anyhow, bail, Context, Result};
 use ringbuf::{HeapProducer, HeapRb};
+use symphonia::core::io::MediaSource;
 use std::{
     ops::{Add, Mul},
     sync::{
@@ -25,7 +69,7 @@ fn buffer_is_full<E>(_: E) -> anyhow::Error {
     anyhow!("buffer is full")
 }
 
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct Frame(pub f32, pub f32);
 impl Frame {
     #[inline(always)]
@@ -56,7 +100,7 @@ impl Mul<f32> for Frame {
     }
 }
 
-const LATENCY_RECORD_NUM: usize = 64;
+pub(crate) const LATENCY_RECORD_NUM: usize = 64;
 
 pub struct LatencyRecorder {
     records: [f32; LATENCY_RECORD_NUM],
@@ -64,6 +108,7 @@ pub struct LatencyRecorder {
     sum: f32,
     full: bool,
     result: Arc<AtomicU32>,
+    stats: Option<LatencyStatsRecorder>,
 }
 
 impl LatencyRecorder {
@@ -74,9 +119,15 @@ impl LatencyRecorder {
             sum: 0.,
             full: false,
             result,
+            stats: None,
         }
     }
 
+    pub(crate) fn with_stats(mut self, stats: LatencyStatsRecorder) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
     #[inline(always)]
     pub fn push(&mut self, record: f32) {
         let idx = self.head;
@@ -91,13 +142,22 @@ impl LatencyRecorder {
         let count = if self.full { LATENCY_RECORD_NUM } else { self.head.max(1) } as f32;
         // low use Ordering::Relaxed
         self.result.store((self.sum / count).to_bits(), Ordering::Relaxed);
+        if let Some(stats) = &mut self.stats {
+            stats.push(record, &self.records, count as usize);
+        }
     }
 }
 
 pub struct AudioManager {
     backend: Box<dyn Backend>,
     latency: Arc<AtomicU32>,
+    clock: AudioClock,
+    stats: Arc<StatsState>,
+    sample_rate: Arc<AtomicU32>,
     prod: HeapProducer<MixerCommand>,
+    offline: Option<ManualHandle>,
+    auto_resample: Option<ResampleQuality>,
+    auto_normalize: Option<f32>,
 }
 
 impl AudioManager {
@@ -108,43 +168,202 @@ impl AudioManager {
     pub fn new_box(mut backend: Box<dyn Backend>) -> Result<Self> {
         let (prod, cons) = HeapRb::new(16).split();
         let latency = Arc::default();
-        let latency_rec = LatencyRecorder::new(Arc::clone(&latency));
+        let stats = Arc::<StatsState>::default();
+        let latency_rec = LatencyRecorder::new(Arc::clone(&latency))
+            .with_stats(LatencyStatsRecorder::new(Arc::clone(&stats)));
+        let (clock, clock_rec) = AudioClock::new(Arc::clone(&latency));
+        let sample_rate = Arc::<AtomicU32>::default();
         backend.setup(BackendSetup {
             mixer_cons: cons,
             latency_rec,
+            clock_rec,
+            stats_rec: StatsRecorder::new(Arc::clone(&stats)),
+            stats: Arc::clone(&stats),
+            sample_rate: Arc::clone(&sample_rate),
         })?;
         backend.start()?;
         Ok(Self {
             backend,
             latency,
+            clock,
+            stats,
+            sample_rate,
             prod,
+            offline: None,
+            auto_resample: None,
+            auto_normalize: None,
         })
     }
 
+    /// Creates a manager that is not connected to any device and can only be rendered through
+    /// [`AudioManager::render_offline`].
+    pub fn new_offline() -> Result<Self> {
+        let backend = ManualBackend::new(ManualSettings::default());
+        let handle = backend.handle();
+        let mut manager = Self::new(backend)?;
+        manager.offline = Some(handle);
+        Ok(manager)
+    }
+
+    /// Converts the clips of sfx and music created afterwards to the output sample rate, so they
+    /// don't have to be converted with linear interpolation during playback. `None` turns this
+    /// off.
+    ///
+    /// The output rate is the one the backend started its stream with. Managers created by
+    /// [`AudioManager::new_offline`] use the rate of the last [`AudioManager::render_offline`],
+    /// 48 kHz before the first one, so a session should render at a single rate.
+    pub fn set_auto_resample(&mut self, quality: Option<ResampleQuality>) {
+        self.auto_resample = quality;
+    }
+
+    /// Sets the gain of the clips of sfx and music created afterwards so that they play at an
+    /// integrated loudness of `target` LUFS. The frames are left as they are. `None` turns this
+    /// off.
+    ///
+    /// Each clip is measured once, so creating more sfx or music from the same clip or its clones
+    /// doesn't measure it again.
+    pub fn set_auto_normalize(&mut self, target: Option<f32>) {
+        self.auto_normalize = target;
+    }
+
+    fn prepare_clip(&self, clip: AudioClip) -> AudioClip {
+        // measured before resampling, which makes a new clip every time
+        let gain = self.auto_normalize.and_then(|it| clip.gain_to(it));
+        let clip = match (self.auto_resample, self.sample_rate.load(Ordering::Relaxed)) {
+            (Some(quality), sample_rate) if sample_rate != 0 => clip.resample(sample_rate, quality),
+            _ => clip,
+        };
+        match gain {
+            Some(gain) => clip.with_gain(gain),
+            None => clip,
+        }
+    }
+
     pub fn create_sfx(&mut self, clip: AudioClip, buffer_size: Option<usize>) -> Result<Sfx> {
-        let (sfx, sfx_renderer) = Sfx::new(clip, buffer_size);
+        let (sfx, sfx_renderer) = Sfx::new(self.prepare_clip(clip), buffer_size);
         self.add_renderer(sfx_renderer)?;
         Ok(sfx)
     }
 
     pub fn create_music(&mut self, clip: AudioClip, settings: MusicParams) -> Result<Music> {
-        let (music, music_renderer) = Music::new(clip, settings);
+        let (music, music_renderer) = Music::new(self.prepare_clip(clip), settings);
+        self.add_renderer(music_renderer)?;
+        Ok(music)
+    }
+
+    /// Creates music that is decoded from `source` while it plays, which avoids decoding long
+    /// tracks up front. Supports the same controls as [`AudioManager::create_music`].
+    ///
+    /// Decoding keeps pace with a real device, so rendering faster than real time through
+    /// [`AudioManager::render_offline`] may run out of decoded audio.
+    pub fn create_streaming_music(
+        &mut self,
+        source: impl MediaSource + 'static,
+        settings: MusicParams,
+    ) -> Result<Music> {
+        self.create_streaming_music_with(source, &DecodeOptions::default(), settings)
+    }
+
+    /// Like [`AudioManager::create_streaming_music`], but picks the format, track and decode
+    /// policy from `options`.
+    pub fn create_streaming_music_with(
+        &mut self,
+        source: impl MediaSource + 'static,
+        options: &DecodeOptions,
+        settings: MusicParams,
+    ) -> Result<Music> {
+        let stats = Arc::clone(&self.stats);
+        let (music, music_renderer) =
+            Music::new_streaming(Box::new(source), options, settings, stats)?;
         self.add_renderer(music_renderer)?;
         Ok(music)
     }
 
+    /// Sets how stereo audio is spread over outputs with more than two channels.
+    pub fn set_upmix(&mut self, upmix: Upmix) -> Result<()> {
+        self.prod
+            .push(MixerCommand::SetUpmix(upmix))
+            .map_err(buffer_is_full)
+            .context("set upmix")?;
+        if let Some(handle) = &self.offline {
+            handle.sync();
+        }
+        Ok(())
+    }
+
     pub fn add_renderer(&mut self, renderer: impl Renderer + 'static) -> Result<()> {
         self.prod
             .push(MixerCommand::AddRenderer(Box::new(renderer)))
             .map_err(buffer_is_full)
             .context("add renderer")?;
+        if let Some(handle) = &self.offline {
+            handle.sync();
+        }
         Ok(())
     }
 
+    /// Renders the next `duration` seconds of stereo output at `sample_rate`, running the actions
+    /// of `timeline` at their scheduled times (relative to the start of this call). Actions
+    /// scheduled after `duration` are dropped.
+    ///
+    /// Rendering happens on the calling thread as fast as possible, and the output only depends on
+    /// the inputs, so identical sessions produce bit-identical clips.
+    pub fn render_offline(
+        &mut self,
+        duration: f32,
+        sample_rate: u32,
+        timeline: Timeline,
+    ) -> Result<AudioClip> {
+        let Some(handle) = &self.offline else {
+            bail!("offline rendering requires a manager created by `AudioManager::new_offline`");
+        };
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+        let settings = ManualSettings {
+            sample_rate,
+            ..ManualSettings::default()
+        };
+        let to_frame = |time: f32| (time.max(0.) as f64 * sample_rate as f64).round() as usize;
+        let total = to_frame(duration);
+
+        let mut frames = Vec::with_capacity(total);
+        for (time, action) in timeline.into_events() {
+            let at = to_frame(time);
+            if at > total {
+                break;
+            }
+            frames.extend(handle.render_with(at - frames.len(), &settings));
+            action().context("timeline action")?;
+        }
+        frames.extend(handle.render_with(total - frames.len(), &settings));
+        Ok(AudioClip::from_raw(frames, sample_rate))
+    }
+
     pub fn estimate_latency(&self) -> f32 {
         f32::from_bits(self.latency.load(Ordering::SeqCst))
     }
 
+    /// Returns a clock that tells which part of the output is being heard. It can be cloned and
+    /// used from any thread.
+    pub fn clock(&self) -> AudioClock {
+        self.clock.clone()
+    }
+
+    /// Returns latency and callback timing statistics collected since the start or the last
+    /// [`AudioManager::reset_stats`].
+    pub fn stats(&self) -> AudioStats {
+        self.stats.snapshot()
+    }
+
+    /// Returns the underruns, overruns and stream errors detected since the start or the last
+    /// [`AudioManager::reset_stats`].
+    pub fn xruns(&self) -> XrunReport {
+        self.stats.xruns.report()
+    }
+
+    pub fn reset_stats(&self) {
+        self.stats.reset();
+    }
+
     #[inline(always)]
     pub fn consume_broken(&self) -> bool {
         self.backend.consume_broken()
@@ -155,6 +374,26 @@ impl AudioManager {
         self.backend.start()
     }
 
+    #[inline(always)]
+    pub fn stop(&mut self) -> Result<()> {
+        self.backend.stop()
+    }
+
+    #[inline(always)]
+    pub fn suspend(&mut self) -> Result<()> {
+        self.backend.suspend()
+    }
+
+    #[inline(always)]
+    pub fn resume(&mut self) -> Result<()> {
+        self.backend.resume()
+    }
+
+    #[inline(always)]
+    pub fn state(&self) -> BackendState {
+        self.backend.state()
+    }
+
     pub fn recover_if_needed(&mut self) -> Result<()> {
         if self.consume_broken() {
             self.start()
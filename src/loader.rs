@@ -0,0 +1,292 @@
+use crate::{AudioClip, DecodeOptions, DecodePolicy, DecodeReport, FormatHint};
+use anyhow::{Context, Result};
+use std::{
+    fs::File,
+    io,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+use symphonia::core::io::MediaSource;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum LoadError {
+    #[error("cannot open `{}`", .0.display())]
+    Open(PathBuf, #[source] io::Error),
+    #[error("cannot decode clip: {0:#}")]
+    Decode(anyhow::Error),
+    #[error("loading was cancelled")]
+    Cancelled,
+    /// Decoding panicked, which is a bug in a decoder. The worker survives it.
+    #[error("decoder panicked: {0}")]
+    Panicked(String),
+}
+
+/// A loaded clip, with what went wrong while decoding it under the loader's
+/// [`DecodePolicy`].
+#[derive(Clone)]
+pub struct LoadedClip {
+    pub clip: AudioClip,
+    pub report: DecodeReport,
+}
+
+#[derive(Debug, Clone)]
+pub struct LoaderSettings {
+    /// Number of clips decoded at the same time.
+    pub workers: usize,
+    pub policy: DecodePolicy,
+}
+impl Default for LoaderSettings {
+    fn default() -> Self {
+        Self {
+            workers: thread::available_parallelism().map_or(2, |it| it.get().min(4)),
+            policy: DecodePolicy::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoadProgress {
+    /// Number of packets decoded so far.
+    pub packets: u64,
+    /// Size of the packets decoded so far, in bytes.
+    pub bytes: u64,
+    /// Size of the whole source in bytes, if known. Container overhead means `bytes` never quite
+    /// reaches it.
+    pub total_bytes: Option<u64>,
+}
+
+impl LoadProgress {
+    /// Estimated fraction of the clip decoded so far, from 0 to 1.
+    pub fn fraction(&self) -> Option<f32> {
+        self.total_bytes
+            .filter(|it| *it != 0)
+            .map(|total| (self.bytes as f64 / total as f64).min(1.) as f32)
+    }
+}
+
+enum Slot {
+    Pending,
+    Done(Result<LoadedClip, LoadError>),
+    Taken,
+}
+
+struct Task {
+    cancelled: AtomicBool,
+    packets: AtomicU64,
+    bytes: AtomicU64,
+    /// `u64::MAX` if unknown.
+    total_bytes: AtomicU64,
+    slot: Mutex<Slot>,
+    done: Condvar,
+}
+
+impl Task {
+    fn finish(&self, result: Result<LoadedClip, LoadError>) {
+        *self.slot.lock().unwrap() = Slot::Done(result);
+        self.done.notify_all();
+    }
+}
+
+/// A clip being loaded by a [`ClipLoader`].
+pub struct ClipHandle(Arc<Task>);
+
+impl ClipHandle {
+    pub fn progress(&self) -> LoadProgress {
+        let total_bytes = self.0.total_bytes.load(Ordering::Relaxed);
+        LoadProgress {
+            packets: self.0.packets.load(Ordering::Relaxed),
+            bytes: self.0.bytes.load(Ordering::Relaxed),
+            total_bytes: (total_bytes != u64::MAX).then_some(total_bytes),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        !matches!(*self.0.slot.lock().unwrap(), Slot::Pending)
+    }
+
+    /// Stops loading the clip. The handle then resolves to [`LoadError::Cancelled`], unless it
+    /// already finished.
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns the result if loading finished. The result is only returned once.
+    pub fn poll(&mut self) -> Option<Result<LoadedClip, LoadError>> {
+        let mut slot = self.0.slot.lock().unwrap();
+        match std::mem::replace(&mut *slot, Slot::Taken) {
+            Slot::Done(result) => Some(result),
+            other => {
+                *slot = other;
+                None
+            }
+        }
+    }
+
+    /// Blocks until loading finished.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the result was already returned by [`ClipHandle::poll`].
+    pub fn wait(self) -> Result<LoadedClip, LoadError> {
+        let mut slot = self.0.slot.lock().unwrap();
+        loop {
+            match std::mem::replace(&mut *slot, Slot::Taken) {
+                Slot::Pending => {
+                    *slot = Slot::Pending;
+                    slot = self.0.done.wait(slot).unwrap();
+                }
+                Slot::Done(result) => return result,
+                Slot::Taken => panic!("result of the clip was already taken"),
+            }
+        }
+    }
+}
+
+enum Source {
+    Path(PathBuf),
+    Reader(Box<dyn MediaSource>),
+}
+
+struct Job {
+    source: Source,
+    options: DecodeOptions,
+    task: Arc<Task>,
+}
+
+fn run_job(job: Job, shutdown: &AtomicBool) -> Result<LoadedClip, LoadError> {
+    let task = &job.task;
+    let is_cancelled =
+        || task.cancelled.load(Ordering::Relaxed) || shutdown.load(Ordering::Relaxed);
+    if is_cancelled() {
+        return Err(LoadError::Cancelled);
+    }
+    let source: Box<dyn MediaSource> = match job.source {
+        Source::Path(path) => match File::open(&path) {
+            Ok(file) => Box::new(file),
+            Err(err) => return Err(LoadError::Open(path, err)),
+        },
+        Source::Reader(reader) => reader,
+    };
+    if let Some(len) = source.byte_len() {
+        task.total_bytes.store(len, Ordering::Relaxed);
+    }
+    let mut progress = |bytes: usize| {
+        task.packets.fetch_add(1, Ordering::Relaxed);
+        task.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        !is_cancelled()
+    };
+    match AudioClip::decode_with_progress(source, &job.options, &mut progress) {
+        Ok((frames, sample_rate, report)) => Ok(LoadedClip {
+            clip: AudioClip::from_raw(frames, sample_rate),
+            report,
+        }),
+        Err(_) if is_cancelled() => Err(LoadError::Cancelled),
+        Err(err) => Err(LoadError::Decode(err)),
+    }
+}
+
+fn worker(jobs: Arc<Mutex<Receiver<Job>>>, shutdown: Arc<AtomicBool>) {
+    loop {
+        let job = jobs.lock().unwrap().recv();
+        let Ok(job) = job else {
+            break;
+        };
+        let task = Arc::clone(&job.task);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| run_job(job, &shutdown)))
+            .unwrap_or_else(|payload| {
+                let message = match payload.downcast::<String>() {
+                    Ok(message) => *message,
+                    Err(payload) => payload
+                        .downcast_ref::<&str>()
+                        .map_or_else(|| "unknown panic".to_owned(), |it| (*it).to_owned()),
+                };
+                Err(LoadError::Panicked(message))
+            });
+        task.finish(result);
+    }
+}
+
+/// Decodes clips on a pool of background threads.
+///
+/// Dropping the loader cancels everything that is still loading.
+pub struct ClipLoader {
+    sender: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    shutdown: Arc<AtomicBool>,
+    policy: DecodePolicy,
+}
+
+impl ClipLoader {
+    pub fn new(settings: LoaderSettings) -> Result<Self> {
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let shutdown = Arc::<AtomicBool>::default();
+        let workers = (0..settings.workers.max(1))
+            .map(|i| {
+                let receiver = Arc::clone(&receiver);
+                let shutdown = Arc::clone(&shutdown);
+                thread::Builder::new()
+                    .name(format!("sasa-loader-{i}"))
+                    .spawn(move || worker(receiver, shutdown))
+                    .context("failed to spawn loader thread")
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            sender: Some(sender),
+            workers,
+            shutdown,
+            policy: settings.policy,
+        })
+    }
+
+    fn submit(&self, source: Source, hint: FormatHint) -> ClipHandle {
+        let task = Arc::new(Task {
+            cancelled: AtomicBool::new(false),
+            packets: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            total_bytes: AtomicU64::new(u64::MAX),
+            slot: Mutex::new(Slot::Pending),
+            done: Condvar::new(),
+        });
+        let job = Job {
+            source,
+            options: DecodeOptions {
+                hint,
+                policy: self.policy,
+                ..DecodeOptions::default()
+            },
+            task: Arc::clone(&task),
+        };
+        // the workers only stop when the loader is dropped
+        let _ = self.sender.as_ref().unwrap().send(job);
+        ClipHandle(task)
+    }
+
+    /// Loads the file at `path`, guessing its format from the extension first.
+    pub fn load(&self, path: impl Into<PathBuf>) -> ClipHandle {
+        let path = path.into();
+        let hint = FormatHint::from_path(&path);
+        self.submit(Source::Path(path), hint)
+    }
+
+    pub fn load_source(&self, source: impl MediaSource + 'static, hint: FormatHint) -> ClipHandle {
+        self.submit(Source::Reader(Box::new(source)), hint)
+    }
+}
+
+impl Drop for ClipLoader {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        self.sender = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
@@ -1,29 +1,125 @@
 use ringbuf::HeapConsumer;
-use crate::Renderer;
+use crate::{clock::ClockRecorder, stats::StatsRecorder, Frame, Renderer};
+use std::time::Instant;
+
+/// Frames the upmix buffer holds unless a backend asks for more with [`Mixer::prepare`]. Longer
+/// buffers are rendered in several passes.
+const DEFAULT_MAX_FRAMES: usize = 4096;
 
 pub(crate) enum MixerCommand {
     AddRenderer(Box<dyn Renderer>),
+    SetUpmix(Upmix),
+}
+
+/// How stereo audio is spread over outputs with more than two channels.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Upmix {
+    /// Only the front left and right channels are used. Adding the signal to more channels would
+    /// shift the stereo image and raise the overall loudness.
+    #[default]
+    Front,
+    /// Also feeds the center with the mid signal and the surround and back channels with the
+    /// matching side, all 3 dB down. The LFE channel stays silent.
+    Surround,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Speaker {
+    FrontLeft,
+    FrontRight,
+    Center,
+    Lfe,
+    BackCenter,
+    /// Surround or back left.
+    RearLeft,
+    /// Surround or back right.
+    RearRight,
+}
+
+/// Speaker positions in the usual channel orders of WAVE and SMPTE, which is what devices use.
+/// Channels of unknown layouts beyond the front pair are left silent.
+fn speakers(channels: u16) -> &'static [Speaker] {
+    use Speaker::*;
+    match channels {
+        3 => &[FrontLeft, FrontRight, Center],
+        4 => &[FrontLeft, FrontRight, RearLeft, RearRight],
+        5 => &[FrontLeft, FrontRight, Center, RearLeft, RearRight],
+        6 => &[FrontLeft, FrontRight, Center, Lfe, RearLeft, RearRight],
+        7 => &[FrontLeft, FrontRight, Center, Lfe, BackCenter, RearLeft, RearRight],
+        8 => &[FrontLeft, FrontRight, Center, Lfe, RearLeft, RearRight, RearLeft, RearRight],
+        _ => &[FrontLeft, FrontRight],
+    }
+}
+
+/// Channel count and upmix mode of the output, passed to
+/// [`Renderer::render_multichannel`](crate::Renderer::render_multichannel).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelLayout {
+    pub channels: u16,
+    pub upmix: Upmix,
+}
+
+impl ChannelLayout {
+    /// Adds a stereo frame to `out`, one output frame of this layout.
+    #[inline]
+    pub fn mix_stereo(&self, frame: Frame, out: &mut [f32]) {
+        const HALF_POWER: f32 = std::f32::consts::FRAC_1_SQRT_2;
+        for (out, speaker) in out.iter_mut().zip(speakers(self.channels)) {
+            *out += match (speaker, self.upmix) {
+                (Speaker::FrontLeft, _) => frame.0,
+                (Speaker::FrontRight, _) => frame.1,
+                (_, Upmix::Front) => continue,
+                (Speaker::Center | Speaker::BackCenter, _) => frame.avg() * HALF_POWER,
+                (Speaker::RearLeft, _) => frame.0 * HALF_POWER,
+                (Speaker::RearRight, _) => frame.1 * HALF_POWER,
+                (Speaker::Lfe, _) => continue,
+            };
+        }
+    }
 }
 
 pub(crate) struct Mixer {
     pub(crate) sample_rate: u32,
     renderers: Vec<Box<dyn Renderer>>,
     cons: HeapConsumer<MixerCommand>,
+    upmix: Upmix,
+    stereo_buffer: Vec<f32>,
+    clock: ClockRecorder,
+    stats: StatsRecorder,
 }
 
 impl Mixer {
-    pub(crate) fn new(sample_rate: u32, cons: HeapConsumer<MixerCommand>) -> Self {
+    pub(crate) fn new(
+        sample_rate: u32,
+        cons: HeapConsumer<MixerCommand>,
+        clock: ClockRecorder,
+        stats: StatsRecorder,
+    ) -> Self {
         Self {
             sample_rate,
             renderers: Vec::new(),
             cons,
+            upmix: Upmix::default(),
+            stereo_buffer: vec![0.; DEFAULT_MAX_FRAMES * 2],
+            clock,
+            stats,
         }
     }
 
-    fn consume_commands(&mut self) {
+    /// Sizes the buffers for callbacks of up to `max_frames` frames, so that rendering them
+    /// doesn't allocate. Must be called outside of the audio callback.
+    pub(crate) fn prepare(&mut self, max_frames: usize) {
+        let len = max_frames.max(DEFAULT_MAX_FRAMES) * 2;
+        if self.stereo_buffer.len() < len {
+            self.stereo_buffer.resize(len, 0.);
+        }
+    }
+
+    pub(crate) fn consume_commands(&mut self) {
         for cmd in self.cons.pop_iter() {
-            if let MixerCommand::AddRenderer(renderer) = cmd {
-                self.renderers.push(renderer);
+            match cmd {
+                MixerCommand::AddRenderer(renderer) => self.renderers.push(renderer),
+                MixerCommand::SetUpmix(upmix) => self.upmix = upmix,
             }
         }
     }
@@ -47,4 +143,60 @@ impl Mixer {
             renderer.alive()
         });
     }
+
+    /// Renders interleaved audio for a device with `channels` channels.
+    pub fn render(&mut self, channels: u16, data: &mut [f32]) {
+        self.render_timed(channels, data, None);
+    }
+
+    /// Like [`Mixer::render`], with the time the backend invoked the callback, in seconds on a
+    /// clock of its own. See [`ClockRecorder::push`].
+    pub fn render_timed(&mut self, channels: u16, data: &mut [f32], timestamp: Option<f64>) {
+        if channels == 0 {
+            data.fill(0.);
+            return;
+        }
+        let start = Instant::now();
+        let frames = data.len() / channels as usize;
+        self.clock.push(frames, self.sample_rate, timestamp);
+        match channels {
+            1 => self.render_mono(data),
+            2 => self.render_stereo(data),
+            _ => self.render_multichannel(channels, data),
+        }
+        self.stats.push(start, frames, self.sample_rate);
+    }
+
+    /// Renderers that only produce stereo are rendered into a scratch buffer, which is then
+    /// upmixed according to the [`Upmix`] mode.
+    fn render_multichannel(&mut self, channels: u16, data: &mut [f32]) {
+        self.consume_commands();
+        data.fill(0.);
+
+        let layout = ChannelLayout {
+            channels,
+            upmix: self.upmix,
+        };
+        let channels = channels as usize;
+        let max_frames = self.stereo_buffer.len() / 2;
+        for data in data.chunks_mut(max_frames * channels) {
+            let frames = data.len() / channels;
+            let stereo = &mut self.stereo_buffer[..frames * 2];
+            stereo.fill(0.);
+            let mut upmix = false;
+            self.renderers.retain_mut(|renderer| {
+                if !renderer.render_multichannel(self.sample_rate, layout, data) {
+                    renderer.render_stereo(self.sample_rate, stereo);
+                    upmix = true;
+                }
+                renderer.alive()
+            });
+
+            if upmix {
+                for (out, frame) in data.chunks_exact_mut(channels).zip(stereo.chunks_exact(2)) {
+                    layout.mix_stereo(Frame(frame[0], frame[1]), out);
+                }
+            }
+        }
+    }
 }
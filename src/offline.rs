@@ -0,0 +1,32 @@
+use anyhow::Result;
+
+type Action<'a> = Box<dyn FnOnce() -> Result<()> + 'a>;
+
+/// A list of actions to perform at given points of an offline render, see
+/// [`AudioManager::render_offline`](crate::AudioManager::render_offline).
+///
+/// Actions usually forward to [`Music`](crate::Music) or [`Sfx`](crate::Sfx) handles. Since
+/// several actions may need the same handle, wrap it in a `RefCell` and borrow it inside each
+/// action.
+#[derive(Default)]
+pub struct Timeline<'a> {
+    events: Vec<(f32, Action<'a>)>,
+}
+
+impl<'a> Timeline<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Schedules `action` at `time` seconds from the start of the render. Actions with the same
+    /// time run in the order they were added.
+    pub fn at(&mut self, time: f32, action: impl FnOnce() -> Result<()> + 'a) -> &mut Self {
+        self.events.push((time, Box::new(action)));
+        self
+    }
+
+    pub(crate) fn into_events(mut self) -> Vec<(f32, Action<'a>)> {
+        self.events.sort_by(|a, b| a.0.total_cmp(&b.0));
+        self.events
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{clip::track_info, TrackInfo};
+use anyhow::{anyhow, Result};
+use std::io::ErrorKind;
+use symphonia::core::{
+    errors::Error,
+    meta::{MetadataRevision, StandardTagKey, StandardVisualKey},
+    probe::ProbeResult,
+};
+
+/// A picture embedded in a media file, e.g. cover art.
+#[derive(Debug, Clone)]
+pub struct Picture {
+    /// MIME type of `data`, e.g. `image/jpeg`.
+    pub media_type: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub is_front_cover: bool,
+    pub data: Box<[u8]>,
+}
+
+/// What [`AudioClip::probe`](crate::AudioClip::probe) found out about a media file.
+#[derive(Debug, Clone)]
+pub struct MediaInfo {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    /// All tags as `(key, value)`, including the ones above, with keys as they appear in the file.
+    pub tags: Vec<(String, String)>,
+    pub pictures: Vec<Picture>,
+    /// The track [`AudioClip::decode`](crate::AudioClip::decode) decodes. Its duration is always
+    /// known, counting packets if the container doesn't tell it.
+    pub track: TrackInfo,
+}
+
+impl MediaInfo {
+    /// Returns the front cover, or the first picture if none is marked as such.
+    pub fn cover(&self) -> Option<&Picture> {
+        self.pictures
+            .iter()
+            .find(|it| it.is_front_cover)
+            .or_else(|| self.pictures.first())
+    }
+
+    fn add_revision(&mut self, revision: &MetadataRevision) {
+        for tag in revision.tags() {
+            // RIFF INFO strings keep their terminator
+            let value = tag.value.to_string().trim_end_matches('\0').to_owned();
+            let slot = match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => Some(&mut self.title),
+                Some(StandardTagKey::Artist) => Some(&mut self.artist),
+                Some(StandardTagKey::Album) => Some(&mut self.album),
+                _ => None,
+            };
+            if let Some(slot) = slot {
+                slot.get_or_insert_with(|| value.clone());
+            }
+            self.tags.push((tag.key.clone(), value));
+        }
+        self.pictures
+            .extend(revision.visuals().iter().map(|visual| Picture {
+                media_type: visual.media_type.clone(),
+                width: visual.dimensions.map(|it| it.width),
+                height: visual.dimensions.map(|it| it.height),
+                is_front_cover: visual.usage == Some(StandardVisualKey::FrontCover),
+                data: visual.data.clone(),
+            }));
+    }
+}
+
+pub(crate) fn probe(mut probed: ProbeResult) -> Result<MediaInfo> {
+    let format = &mut probed.format;
+    let track = format
+        .default_track()
+        .ok_or_else(|| anyhow!("default track not found"))?;
+    let track_id = track.id;
+    let time_base = track.codec_params.time_base;
+    let mut info = MediaInfo {
+        title: None,
+        artist: None,
+        album: None,
+        tags: Vec::new(),
+        pictures: Vec::new(),
+        track: track_info(track, Some(track_id)),
+    };
+
+    // tags found before the container (e.g. ID3v2) come first, then the container's own
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|it| it.current()) {
+        info.add_revision(revision);
+    }
+    if let Some(revision) = format.metadata().current() {
+        info.add_revision(revision);
+    }
+
+    if info.track.duration.is_none() {
+        let mut duration = 0;
+        loop {
+            match format.next_packet() {
+                Ok(packet) if packet.track_id() == track_id => duration += packet.dur,
+                Ok(_) => {}
+                Err(Error::IoError(err)) if err.kind() == ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        info.track.duration = match (time_base, info.track.sample_rate) {
+            (Some(time_base), _) => {
+                let time = time_base.calc_time(duration);
+                Some((time.seconds as f64 + time.frac) as f32)
+            }
+            (None, Some(sample_rate)) => Some(duration as f32 / sample_rate as f32),
+            (None, None) => None,
+        };
+    }
+    Ok(info)
+}
@@ -1,6 +1,10 @@
+use crate::ChannelLayout;
+
 mod music;
 pub use music::{Music, MusicParams};
 
+mod stream;
+
 mod sfx;
 pub use sfx::{Sfx, PlaySfxParams};
 
@@ -8,4 +12,16 @@ pub trait Renderer: Send + Sync {
     fn alive(&self) -> bool;
     fn render_mono(&mut self, sample_rate: u32, data: &mut [f32]);
     fn render_stereo(&mut self, sample_rate: u32, data: &mut [f32]);
+
+    /// Renders interleaved audio with more than two channels. Returns `false` if the renderer
+    /// only produces stereo, in which case the mixer renders it through
+    /// [`Renderer::render_stereo`] and upmixes the result instead.
+    fn render_multichannel(
+        &mut self,
+        _sample_rate: u32,
+        _layout: ChannelLayout,
+        _data: &mut [f32],
+    ) -> bool {
+        false
+    }
 }
@@ -1,4 +1,4 @@
-use crate::{buffer_is_full, AudioClip, Frame, Renderer};
+use crate::{buffer_is_full, AudioClip, Frame, Interpolation, Renderer};
 use anyhow::{Context, Result};
 use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
 use std::sync::{
@@ -12,6 +12,11 @@ pub struct MusicParams {
     pub amplifier: f32,
     pub playback_rate: f32,
     pub command_buffer_size: usize,
+    /// Capacity in frames of the buffer between the decoding thread and the renderer of
+    /// streamed music. Larger buffers survive longer decoder stalls.
+    pub stream_buffer_size: usize,
+    /// Overrides the interpolation of the clip. Streamed music always interpolates linearly.
+    pub interpolation: Option<Interpolation>,
 }
 impl Default for MusicParams {
     fn default() -> Self {
@@ -20,13 +25,15 @@ impl Default for MusicParams {
             amplifier: 1.,
             playback_rate: 1.,
             command_buffer_size: 16,
+            stream_buffer_size: 1 << 16,
+            interpolation: None,
         }
     }
 }
 
-struct SharedState {
-    position: AtomicU32, // float in bits
-    paused: AtomicBool,
+pub(super) struct SharedState {
+    pub position: AtomicU32, // float in bits
+    pub paused: AtomicBool,
 }
 impl Default for SharedState {
     fn default() -> Self {
@@ -37,7 +44,7 @@ impl Default for SharedState {
     }
 }
 
-enum MusicCommand {
+pub(super) enum MusicCommand {
     Pause,
     Resume,
     SetAmplifier(f32),
@@ -47,112 +54,167 @@ enum MusicCommand {
     FadeOut(f32),
 }
 
-pub(crate) struct MusicRenderer {
-    clip: AudioClip,
-    settings: MusicParams,
+/// Playback controls shared by the renderers of clip-based and streamed music: the command queue,
+/// pausing, fades, the amplifier and the low-pass filter.
+pub(super) struct Controls {
+    pub settings: MusicParams,
     state: Weak<SharedState>,
     cons: HeapConsumer<MusicCommand>,
-    paused: bool,
-    index: usize,
-    last_sample_rate: u32,
+    pub paused: bool,
+    /// The output sample rate of the latest callback.
+    pub sample_rate: u32,
     low_pass: f32,
     last_output: Frame,
-    
-    fade_type: u8, // 0: 无, 1: 淡入, 2: 淡出
+
+    fade_type: u8, // 0: none, 1: fade in, 2: fade out
     fade_samples: u32,
     fade_current: u32,
 }
 
-impl MusicRenderer {
-    fn prepare(&mut self, sample_rate: u32) {
-        if self.last_sample_rate != sample_rate {
-            let factor = sample_rate as f32 / self.last_sample_rate as f32;
-            self.index = (self.index as f32 * factor).round() as _;
-            self.last_sample_rate = sample_rate;
-            self.fade_samples = (self.fade_samples as f32 * factor).round() as _;
-            self.fade_current = (self.fade_current as f32 * factor).round() as _;
+impl Controls {
+    pub fn new(
+        settings: MusicParams,
+        state: Weak<SharedState>,
+        cons: HeapConsumer<MusicCommand>,
+    ) -> Self {
+        Self {
+            settings,
+            state,
+            cons,
+            paused: true,
+            sample_rate: 1,
+            low_pass: 0.,
+            last_output: Frame::default(),
+
+            fade_type: 0,
+            fade_samples: 0,
+            fade_current: 0,
+        }
+    }
+
+    pub fn alive(&self) -> bool {
+        self.state.strong_count() != 0
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        if let Some(state) = self.state.upgrade() {
+            state.paused.store(paused, Ordering::SeqCst);
         }
-        
-        for cmd in self.cons.pop_iter() {
+    }
+
+    /// Stops at the end of the track. Unlike pausing, this isn't reported by [`Music::paused`].
+    pub fn end(&mut self) {
+        self.paused = true;
+    }
+
+    /// Adapts to the output sample rate of a callback, returning its ratio to the previous one if
+    /// it changed.
+    pub fn update_sample_rate(&mut self, sample_rate: u32) -> Option<f32> {
+        if self.sample_rate == sample_rate {
+            return None;
+        }
+        let factor = sample_rate as f32 / self.sample_rate as f32;
+        self.sample_rate = sample_rate;
+        self.fade_samples = (self.fade_samples as f32 * factor).round() as _;
+        self.fade_current = (self.fade_current as f32 * factor).round() as _;
+        Some(factor)
+    }
+
+    /// Applies pending commands. Seeks are left to `seek`, which gets the position in seconds.
+    pub fn consume_commands(&mut self, mut seek: impl FnMut(&MusicParams, f32)) {
+        while let Some(cmd) = self.cons.pop() {
             match cmd {
-                MusicCommand::Pause => {
-                    self.paused = true;
-                    if let Some(state) = self.state.upgrade() {
-                        state.paused.store(true, Ordering::SeqCst);
-                    }
-                }
-                MusicCommand::Resume => {
-                    self.paused = false;
-                    if let Some(state) = self.state.upgrade() {
-                        state.paused.store(false, Ordering::SeqCst);
-                    }
-                }
+                MusicCommand::Pause => self.set_paused(true),
+                MusicCommand::Resume => self.set_paused(false),
                 MusicCommand::SetAmplifier(amp) => {
                     self.settings.amplifier = amp;
                 }
-                MusicCommand::SeekTo(position) => {
-                    self.index = (position * sample_rate as f32 / self.settings.playback_rate)
-                        .round() as usize;
-                }
+                MusicCommand::SeekTo(position) => seek(&self.settings, position),
                 MusicCommand::SetLowPass(low_pass) => {
                     self.low_pass = low_pass;
                 }
                 MusicCommand::FadeIn(time) => {
                     if self.paused {
-                        self.paused = false;
-                        if let Some(state) = self.state.upgrade() {
-                            state.paused.store(false, Ordering::SeqCst);
-                        }
+                        self.set_paused(false);
                     }
                     self.fade_type = 1;
-                    self.fade_samples = (time * sample_rate as f32).round() as _;
+                    self.fade_samples = (time * self.sample_rate as f32).round() as _;
                     self.fade_current = 0;
                 }
                 MusicCommand::FadeOut(time) => {
                     self.fade_type = 2;
-                    self.fade_samples = (time * sample_rate as f32).round() as _;
+                    self.fade_samples = (time * self.sample_rate as f32).round() as _;
                     self.fade_current = 0;
                 }
             }
         }
     }
 
+    /// Returns the amplifier for the next frame, advancing fades. A finished fade out pauses.
     #[inline]
-    fn get_amplifier(&mut self) -> f32 {
+    pub fn amplifier(&mut self) -> f32 {
         if self.fade_type == 0 {
             return self.settings.amplifier;
         }
 
         self.fade_current += 1;
+        let progress = self.fade_current as f32 / self.fade_samples as f32;
         let amp = match self.fade_type {
-            1 => self.settings.amplifier * (self.fade_current as f32 / self.fade_samples as f32),
-            2 => {
-                let progress = self.fade_current as f32 / self.fade_samples as f32;
-                if progress >= 1.0 {
+            1 => self.settings.amplifier * progress.min(1.),
+            _ => {
+                if progress >= 1. {
                     self.fade_type = 0;
-                    self.paused = true;
-                    if let Some(state) = self.state.upgrade() {
-                        state.paused.store(true, Ordering::SeqCst);
-                    }
-                    return 0.0;
+                    self.set_paused(true);
+                    return 0.;
                 }
-                self.settings.amplifier * (1.0 - progress)
+                self.settings.amplifier * (1. - progress)
             }
-            _ => self.settings.amplifier,
         };
-
         if self.fade_current >= self.fade_samples {
             self.fade_type = 0;
         }
-
         amp
     }
 
+    /// Runs `frame` through the low-pass filter.
+    #[inline(always)]
+    pub fn filter(&mut self, frame: Frame) -> Frame {
+        let alpha = 1.0 - self.low_pass;
+        self.last_output.0 = self.low_pass * self.last_output.0 + alpha * frame.0;
+        self.last_output.1 = self.low_pass * self.last_output.1 + alpha * frame.1;
+        self.last_output
+    }
+
+    pub fn store_position(&self, position: f32) {
+        if let Some(state) = self.state.upgrade() {
+            state.position.store(position.to_bits(), Ordering::SeqCst);
+        }
+    }
+}
+
+pub(crate) struct MusicRenderer {
+    clip: AudioClip,
+    controls: Controls,
+    index: usize,
+}
+
+impl MusicRenderer {
+    fn prepare(&mut self, sample_rate: u32) {
+        if let Some(factor) = self.controls.update_sample_rate(sample_rate) {
+            self.index = (self.index as f32 * factor).round() as _;
+        }
+        let index = &mut self.index;
+        self.controls.consume_commands(|settings, position| {
+            *index = (position * sample_rate as f32 / settings.playback_rate).round() as usize;
+        });
+    }
+
     #[inline]
     fn get_frame(&mut self, position: f32) -> Option<Frame> {
-        let amp = self.get_amplifier();
-        let loop_mix_time = self.settings.loop_mix_time;
-        let playback_rate = self.settings.playback_rate;
+        let amp = self.controls.amplifier();
+        let loop_mix_time = self.controls.settings.loop_mix_time;
+        let playback_rate = self.controls.settings.playback_rate;
 
         if let Some(mut frame) = self.clip.sample(position) {
             if loop_mix_time >= 0. {
@@ -167,15 +229,15 @@ impl MusicRenderer {
             Some(frame * amp)
         } else if loop_mix_time >= 0. {
             let position = position - self.clip.length() + loop_mix_time;
-            self.index = (position * self.last_sample_rate as f32 / playback_rate)
-                .round() as usize;
+            self.index =
+                (position * self.controls.sample_rate as f32 / playback_rate).round() as usize;
             Some(if let Some(frame) = self.clip.sample(position) {
                 frame * amp
             } else {
                 Frame::default()
             })
         } else {
-            self.paused = true;
+            self.controls.end();
             None
         }
     }
@@ -185,14 +247,6 @@ impl MusicRenderer {
         self.index as f32 * delta
     }
 
-    #[inline(always)]
-    fn update_and_get(&mut self, frame: Frame) -> Frame {
-        let alpha = 1.0 - self.low_pass;
-        self.last_output.0 = self.low_pass * self.last_output.0 + alpha * frame.0;
-        self.last_output.1 = self.low_pass * self.last_output.1 + alpha * frame.1;
-        self.last_output
-    }
-    
     fn process_block(&mut self, start_pos: f64, delta: f64, samples: &mut [f32], stereo: bool) {
         let block_size = 4; // 4帧块处理
         let mut pos = start_pos;
@@ -205,9 +259,9 @@ impl MusicRenderer {
             let mut frames = [Frame::default(); 4];
             let mut valid_count = 0;
 
-            for j in 0..(to_process / if stereo { 2 } else { 1 }) {
+            for slot in frames.iter_mut().take(to_process / if stereo { 2 } else { 1 }) {
                 if let Some(frame) = self.get_frame(pos as f32) {
-                    frames[j] = self.update_and_get(frame);
+                    *slot = self.controls.filter(frame);
                     valid_count += 1;
                     pos += delta;
                 } else {
@@ -233,23 +287,19 @@ impl MusicRenderer {
             }
         }
 
-        if let Some(state) = self.state.upgrade() {
-            state
-                .position
-                .store(self.position(delta as f32).to_bits(), Ordering::SeqCst);
-        }
+        self.controls.store_position(self.position(delta as f32));
     }
 }
 
 impl Renderer for MusicRenderer {
     fn alive(&self) -> bool {
-        self.state.strong_count() != 0
+        self.controls.alive()
     }
 
     fn render_mono(&mut self, sample_rate: u32, data: &mut [f32]) {
         self.prepare(sample_rate);
-        if !self.paused {
-            let delta = 1. / sample_rate as f64 * self.settings.playback_rate as f64;
+        if !self.controls.paused {
+            let delta = 1. / sample_rate as f64 * self.controls.settings.playback_rate as f64;
             let start_pos = self.index as f64 * delta;
             self.process_block(start_pos, delta, data, false);
         }
@@ -257,8 +307,8 @@ impl Renderer for MusicRenderer {
 
     fn render_stereo(&mut self, sample_rate: u32, data: &mut [f32]) {
         self.prepare(sample_rate);
-        if !self.paused {
-            let delta = 1. / sample_rate as f64 * self.settings.playback_rate as f64;
+        if !self.controls.paused {
+            let delta = 1. / sample_rate as f64 * self.controls.settings.playback_rate as f64;
             let start_pos = self.index as f64 * delta;
             self.process_block(start_pos, delta, data, true);
         }
@@ -271,26 +321,28 @@ pub struct Music {
 }
 
 impl Music {
-    pub(crate) fn new(clip: AudioClip, settings: MusicParams) -> (Music, MusicRenderer) {
+    /// Creates a handle together with the state and command queue its renderer listens to.
+    pub(super) fn channel(
+        settings: &MusicParams,
+    ) -> (Music, Weak<SharedState>, HeapConsumer<MusicCommand>) {
         let (prod, cons) = HeapRb::new(settings.command_buffer_size).split();
         let arc = Arc::default();
+        let state = Arc::downgrade(&arc);
+        (Self { arc, prod }, state, cons)
+    }
+
+    pub(crate) fn new(clip: AudioClip, settings: MusicParams) -> (Music, MusicRenderer) {
+        let (music, state, cons) = Self::channel(&settings);
+        let clip = match settings.interpolation {
+            Some(interpolation) => clip.with_interpolation(interpolation),
+            None => clip,
+        };
         let renderer = MusicRenderer {
             clip,
-            settings,
-            state: Arc::downgrade(&arc),
-            cons,
-            paused: true,
+            controls: Controls::new(settings, state, cons),
             index: 0,
-            last_sample_rate: 1,
-            low_pass: 0.,
-            last_output: Frame(0., 0.),
-
-            // 淡入淡出状态
-            fade_type: 0,
-            fade_samples: 0,
-            fade_current: 0,
         };
-        (Self { arc, prod }, renderer)
+        (music, renderer)
     }
 
     pub fn play(&mut self) -> Result<()> {
@@ -307,6 +359,8 @@ impl Music {
             .context("pause")
     }
 
+    /// Whether the music is paused, by [`Music::pause`] or a finished fade out. Music that
+    /// played to its end stops without being paused.
     pub fn paused(&mut self) -> bool {
         self.arc.paused.load(Ordering::SeqCst)
     }
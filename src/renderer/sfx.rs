@@ -1,4 +1,4 @@
-use crate::{buffer_is_full, AudioClip, Renderer};
+use crate::{buffer_is_full, AudioClip, ChannelLayout, Interpolation, Renderer};
 use anyhow::{Context, Result};
 use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
 use std::sync::{Arc, Weak};
@@ -6,11 +6,16 @@ use std::sync::{Arc, Weak};
 #[derive(Debug, Clone)]
 pub struct PlaySfxParams {
     pub amplifier: f32,
+    /// Overrides the interpolation of the clip.
+    pub interpolation: Option<Interpolation>,
 }
 
 impl Default for PlaySfxParams {
     fn default() -> Self {
-        Self { amplifier: 1. }
+        Self {
+            amplifier: 1.,
+            interpolation: None,
+        }
     }
 }
 
@@ -32,6 +37,7 @@ impl Renderer for SfxRenderer {
 
         for (position, params) in self.cons.iter_mut() {
             let amplifier = params.amplifier;
+            let interpolation = params.interpolation.unwrap_or(clip.interpolation());
             let mut pos = *position;
             let mut buffer_index = 0;
             let total_samples = data.len();
@@ -43,9 +49,9 @@ impl Renderer for SfxRenderer {
                 let mut samples = [0.0; 8];
 
                 // Unroll the sampling loop
-                for i in 0..8 {
-                    if let Some(frame) = clip.sample(pos + delta * i as f32) {
-                        samples[i] = (frame.0 + frame.1) * 0.5 * amplifier;
+                for (i, sample) in samples.iter_mut().enumerate() {
+                    if let Some(frame) = clip.sample_with(pos + delta * i as f32, interpolation) {
+                        *sample = (frame.0 + frame.1) * 0.5 * amplifier;
                     } else {
                         valid = false;
                         break;
@@ -54,8 +60,8 @@ impl Renderer for SfxRenderer {
 
                 if valid {
                     // Batch write to output buffer
-                    for i in 0..8 {
-                        data[buffer_index + i] += samples[i];
+                    for (out, sample) in data[buffer_index..buffer_index + 8].iter_mut().zip(samples) {
+                        *out += sample;
                     }
                     buffer_index += 8;
                     pos += delta * 8.0;
@@ -67,7 +73,7 @@ impl Renderer for SfxRenderer {
             // Process remaining samples (0-7)
             let remaining = total_samples - buffer_index;
             for i in 0..remaining {
-                if let Some(frame) = clip.sample(pos) {
+                if let Some(frame) = clip.sample_with(pos, interpolation) {
                     data[buffer_index + i] += (frame.0 + frame.1) * 0.5 * amplifier;
                     pos += delta;
                 } else {
@@ -93,6 +99,7 @@ impl Renderer for SfxRenderer {
 
         for (position, params) in self.cons.iter_mut() {
             let amplifier = params.amplifier;
+            let interpolation = params.interpolation.unwrap_or(clip.interpolation());
             let mut pos = *position;
             let total_samples = data.len();
             let total_frames = total_samples / 2;
@@ -105,9 +112,9 @@ impl Renderer for SfxRenderer {
                 let mut valid = true;
 
                 // Unroll the sampling loop
-                for i in 0..8 {
-                    if let Some(frame) = clip.sample(pos + delta * i as f32) {
-                        frames[i] = (frame.0, frame.1);
+                for (i, slot) in frames.iter_mut().enumerate() {
+                    if let Some(frame) = clip.sample_with(pos + delta * i as f32, interpolation) {
+                        *slot = (frame.0, frame.1);
                     } else {
                         valid = false;
                         break;
@@ -117,10 +124,10 @@ impl Renderer for SfxRenderer {
                 if valid {
                     let base_index = frame_index * 2;
                     // Write batch to output buffer
-                    for i in 0..8 {
+                    for (i, frame) in frames.iter().enumerate() {
                         let idx = base_index + i * 2;
-                        data[idx] += frames[i].0 * amplifier;
-                        data[idx + 1] += frames[i].1 * amplifier;
+                        data[idx] += frame.0 * amplifier;
+                        data[idx + 1] += frame.1 * amplifier;
                     }
                     frame_index += 8;
                     pos += delta * 8.0;
@@ -132,7 +139,7 @@ impl Renderer for SfxRenderer {
             // Process remaining frames (0-7)
             let remaining = total_frames - frame_index;
             for i in 0..remaining {
-                if let Some(frame) = clip.sample(pos) {
+                if let Some(frame) = clip.sample_with(pos, interpolation) {
                     let idx = (frame_index + i) * 2;
                     data[idx] += frame.0 * amplifier;
                     data[idx + 1] += frame.1 * amplifier;
@@ -152,6 +159,43 @@ impl Renderer for SfxRenderer {
             self.cons.advance(pop_count);
         }
     }
+
+    fn render_multichannel(
+        &mut self,
+        sample_rate: u32,
+        layout: ChannelLayout,
+        data: &mut [f32],
+    ) -> bool {
+        let delta = 1. / sample_rate as f32;
+        let clip = &self.clip;
+
+        for (position, params) in self.cons.iter_mut() {
+            let amplifier = params.amplifier;
+            let interpolation = params.interpolation.unwrap_or(clip.interpolation());
+            let mut pos = *position;
+            for out in data.chunks_exact_mut(layout.channels as usize) {
+                if let Some(frame) = clip.sample_with(pos, interpolation) {
+                    layout.mix_stereo(frame * amplifier, out);
+                    pos += delta;
+                } else {
+                    // finished entries stay silent until the ones before them finish too
+                    pos = f32::INFINITY;
+                    break;
+                }
+            }
+            *position = pos;
+        }
+
+        let pop_count = self
+            .cons
+            .iter()
+            .take_while(|(position, _)| position.is_infinite())
+            .count();
+        unsafe {
+            self.cons.advance(pop_count);
+        }
+        true
+    }
 }
 
 pub struct Sfx {
@@ -172,6 +216,9 @@ impl Sfx {
     }
 
     pub fn play(&mut self, params: PlaySfxParams) -> Result<()> {
+        if let Some(interpolation) = params.interpolation {
+            interpolation.prepare();
+        }
         self.prod
             .push((0., params))
             .map_err(buffer_is_full)
@@ -0,0 +1,455 @@
+use super::music::{Controls, SharedState};
+use crate::{
+    clip::{is_missing_reservoir, load_frames_from_buffer_ref, probe_source, select_track},
+    stats::StatsState,
+    xrun::XrunKind,
+    DecodeOptions, DecodePolicy, Frame, Music, MusicParams, Renderer,
+};
+use anyhow::{anyhow, Context, Result};
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+use std::{
+    collections::VecDeque,
+    io::ErrorKind,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Weak,
+    },
+    thread,
+    time::Duration,
+};
+use symphonia::core::{
+    codecs::Decoder,
+    errors::Error,
+    formats::{FormatReader, SeekMode, SeekTo},
+    io::MediaSource,
+    units::TimeBase,
+};
+
+/// How long the decoding thread sleeps when the buffer is full or there is nothing to decode.
+const IDLE_INTERVAL: Duration = Duration::from_millis(5);
+
+#[derive(Clone, Copy)]
+enum Item {
+    Frame(Frame),
+    /// The track looped, and the next frame has this index.
+    Jump(u64),
+    End,
+}
+
+/// An item tagged with the seek generation it was decoded for. Items of older generations are
+/// leftovers from before a seek and are skipped by the renderer.
+#[derive(Clone, Copy)]
+struct Tagged(u32, Item);
+
+/// The latest seek requested by the renderer. Only the latest one matters, so a new request
+/// simply overwrites the previous one and the decoder never falls behind.
+#[derive(Default)]
+struct SeekRequest {
+    generation: AtomicU32,
+    frame: AtomicU64,
+}
+
+struct StreamDecoder {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    sample_rate: u32,
+    policy: DecodePolicy,
+    time_base: Option<TimeBase>,
+    frame_count: Option<u64>,
+    /// Number of frames the end of the track overlaps with the start of the next loop, or `None`
+    /// if not looping.
+    loop_mix: Option<u64>,
+    /// The first `loop_mix` frames of the track, mixed into its end.
+    head: Vec<Frame>,
+    /// Index of the next decoded frame in the track.
+    index: u64,
+    /// Frames to drop from the next packets, since seeking lands on a packet boundary.
+    skip: u64,
+    generation: u32,
+    buffer: Vec<Frame>,
+    pending: VecDeque<Item>,
+    ended: bool,
+}
+
+impl StreamDecoder {
+    fn open(
+        source: Box<dyn MediaSource>,
+        options: &DecodeOptions,
+        settings: &MusicParams,
+    ) -> Result<Self> {
+        let format = probe_source(source, &options.hint)?.format;
+        let track = select_track(format.as_ref(), &options.track)?;
+        let codec_params = &track.codec_params;
+        let sample_rate = codec_params
+            .sample_rate
+            .ok_or_else(|| anyhow!("unknown sample rate"))?;
+        let decoder = symphonia::default::get_codecs().make(codec_params, &Default::default())?;
+        let loop_mix = (settings.loop_mix_time >= 0.)
+            .then(|| (settings.loop_mix_time as f64 * sample_rate as f64).round() as u64);
+        let mut result = Self {
+            track_id: track.id,
+            sample_rate,
+            policy: options.policy,
+            time_base: codec_params.time_base,
+            frame_count: codec_params.n_frames,
+            format,
+            decoder,
+            loop_mix,
+            head: Vec::new(),
+            index: 0,
+            skip: 0,
+            generation: 0,
+            buffer: Vec::new(),
+            pending: VecDeque::new(),
+            ended: false,
+        };
+        // the head has to be complete before anything could seek past it
+        let head_len = match (result.frame_count, loop_mix) {
+            (Some(_), Some(loop_mix)) => loop_mix as usize,
+            _ => 0,
+        };
+        while result.head.len() < head_len {
+            if !result.decode_packet()? {
+                result.end_of_track()?;
+                break;
+            }
+        }
+        Ok(result)
+    }
+
+    fn ts_to_frames(&self, ts: u64) -> u64 {
+        match self.time_base {
+            Some(tb) => {
+                (ts as u128 * tb.numer as u128 * self.sample_rate as u128 / tb.denom as u128) as u64
+            }
+            None => ts,
+        }
+    }
+
+    fn frames_to_ts(&self, frames: u64) -> u64 {
+        match self.time_base {
+            Some(tb) => {
+                (frames as u128 * tb.denom as u128 / (tb.numer as u128 * self.sample_rate as u128))
+                    as u64
+            }
+            None => frames,
+        }
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<(), Error> {
+        self.pending.clear();
+        self.ended = false;
+        let seeked = self.format.seek(
+            SeekMode::Accurate,
+            SeekTo::TimeStamp {
+                ts: self.frames_to_ts(frame),
+                track_id: self.track_id,
+            },
+        );
+        self.decoder.reset();
+        let seeked = seeked?;
+        self.index = frame;
+        self.skip = self.ts_to_frames(seeked.required_ts.saturating_sub(seeked.actual_ts));
+        Ok(())
+    }
+
+    /// Decodes the next packet of the track into `pending`. Returns `false` at the end of the
+    /// track. Damaged input is handled as [`AudioClip::decode_with`](crate::AudioClip::decode_with)
+    /// does, except that nothing is reported.
+    fn decode_packet(&mut self) -> Result<bool> {
+        let policy = self.policy;
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(Error::IoError(err)) if err.kind() == ErrorKind::UnexpectedEof => {
+                    return Ok(false);
+                }
+                Err(Error::ResetRequired) if policy != DecodePolicy::Strict => return Ok(false),
+                Err(_) if policy == DecodePolicy::BestEffort => return Ok(false),
+                Err(err) => return Err(err.into()),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            let buffer = match self.decoder.decode(&packet) {
+                Ok(buffer) => buffer,
+                Err(err) if is_missing_reservoir(&err) => continue,
+                Err(Error::DecodeError(_) | Error::IoError(_))
+                    if policy != DecodePolicy::Strict =>
+                {
+                    continue
+                }
+                Err(err) => return Err(err.into()),
+            };
+            self.buffer.clear();
+            load_frames_from_buffer_ref(&mut self.buffer, &buffer)?;
+            break;
+        }
+
+        let skip = self.skip.min(self.buffer.len() as u64);
+        self.skip -= skip;
+        let tail_start = match (self.frame_count, self.loop_mix) {
+            (Some(count), Some(loop_mix)) => Some(count.saturating_sub(loop_mix)),
+            _ => None,
+        };
+        for &frame in &self.buffer[skip as usize..] {
+            let mut frame = frame;
+            if (self.head.len() as u64) == self.index
+                && self.loop_mix.is_some_and(|it| self.index < it)
+            {
+                self.head.push(frame);
+            }
+            if let Some(tail_start) = tail_start.filter(|it| self.index >= *it) {
+                if let Some(head) = self.head.get((self.index - tail_start) as usize) {
+                    frame = frame + *head;
+                }
+            }
+            self.index += 1;
+            self.pending.push_back(Item::Frame(frame));
+        }
+        Ok(true)
+    }
+
+    fn end_of_track(&mut self) -> Result<()> {
+        // looping a track without frames would never produce anything
+        if self.loop_mix.is_none() || self.index == 0 {
+            self.pending.push_back(Item::End);
+            self.ended = true;
+            return Ok(());
+        }
+        // the part of the head already mixed into the end of the track is not played again
+        let restart = match self.frame_count {
+            Some(count) => (self.index + self.loop_mix.unwrap())
+                .saturating_sub(count)
+                .min(self.head.len() as u64),
+            None => 0,
+        };
+        self.seek(restart)?;
+        self.pending.push_back(Item::Jump(restart));
+        Ok(())
+    }
+
+    fn run(
+        mut self,
+        mut prod: HeapProducer<Tagged>,
+        seek: Arc<SeekRequest>,
+        state: Weak<SharedState>,
+        stats: Arc<StatsState>,
+    ) {
+        // the renderer holds the other reference
+        while Arc::strong_count(&seek) > 1 && state.strong_count() != 0 {
+            let generation = seek.generation.load(Ordering::Acquire);
+            if generation != self.generation {
+                self.generation = generation;
+                let result = match self.seek(seek.frame.load(Ordering::Relaxed)) {
+                    // seeking past the end
+                    Err(Error::SeekError(_)) => self.end_of_track(),
+                    result => result.map_err(Into::into),
+                };
+                if let Err(err) = result {
+                    stats.xruns.push(XrunKind::StreamError(format!(
+                        "failed to seek music stream: {err}"
+                    )));
+                    self.pending.clear();
+                    self.pending.push_back(Item::End);
+                    self.ended = true;
+                }
+            }
+            while !prod.is_full() {
+                let Some(item) = self.pending.pop_front() else {
+                    break;
+                };
+                let _ = prod.push(Tagged(self.generation, item));
+            }
+            if prod.is_full() || self.ended {
+                thread::sleep(IDLE_INTERVAL);
+                continue;
+            }
+            let result = match self.decode_packet() {
+                Ok(true) => Ok(()),
+                Ok(false) => self.end_of_track(),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result {
+                stats.xruns.push(XrunKind::StreamError(format!(
+                    "failed to decode music stream: {err}"
+                )));
+                self.pending.push_back(Item::End);
+                self.ended = true;
+            }
+        }
+    }
+}
+
+pub(crate) struct StreamingMusicRenderer {
+    controls: Controls,
+    frames: HeapConsumer<Tagged>,
+    seek: Arc<SeekRequest>,
+    generation: u32,
+    /// Sample rate of the track.
+    sample_rate: u32,
+    /// The two frames the output is interpolated between, and how many of them are valid.
+    window: [Frame; 2],
+    filled: usize,
+    /// Index in the track of `window[0]`, and the position between the two frames. The index
+    /// is briefly negative after looping, while the window still holds the end of the track.
+    index: i64,
+    phase: f64,
+    /// Whether the decoder reached the end of the track.
+    ended: bool,
+}
+
+impl StreamingMusicRenderer {
+    fn prepare(&mut self, sample_rate: u32) {
+        self.controls.update_sample_rate(sample_rate);
+        let mut seek_to = None;
+        self.controls
+            .consume_commands(|_, position| seek_to = Some(position));
+        if let Some(position) = seek_to {
+            self.generation = self.generation.wrapping_add(1);
+            let frame = (position.max(0.) as f64 * self.sample_rate as f64).round() as u64;
+            self.index = frame as i64;
+            self.filled = 0;
+            self.phase = 0.;
+            self.ended = false;
+            self.seek.frame.store(frame, Ordering::Relaxed);
+            self.seek
+                .generation
+                .store(self.generation, Ordering::Release);
+        }
+    }
+
+    /// Pops the next item of the current generation, or `None` if the decoder is behind.
+    #[inline]
+    fn pull(&mut self) -> Option<Item> {
+        while let Some(Tagged(generation, item)) = self.frames.pop() {
+            if generation == self.generation {
+                return Some(item);
+            }
+        }
+        None
+    }
+
+    /// Returns the next output frame, advancing by `ratio` track frames. Returns `None` when
+    /// the track ended or the decoder can't keep up.
+    #[inline]
+    fn next_frame(&mut self, ratio: f64) -> Option<Frame> {
+        while self.filled < 2 || self.phase >= 1. {
+            let item = if self.ended { Item::End } else { self.pull()? };
+            match item {
+                Item::Frame(frame) => {
+                    if self.filled == 2 {
+                        self.window[0] = self.window[1];
+                        self.window[1] = frame;
+                        self.phase -= 1.;
+                        self.index += 1;
+                    } else {
+                        self.window[self.filled] = frame;
+                        self.filled += 1;
+                    }
+                }
+                Item::Jump(index) => {
+                    self.index = index as i64 - self.filled as i64;
+                }
+                Item::End if self.filled == 2 && !self.ended => {
+                    // play out the last frame
+                    self.ended = true;
+                    self.window[0] = self.window[1];
+                    self.phase -= 1.;
+                    self.index += 1;
+                }
+                Item::End => {
+                    self.controls.end();
+                    return None;
+                }
+            }
+        }
+        let frame = self.window[0].interpolate(&self.window[1], self.phase as f32);
+        self.phase += ratio;
+        Some(frame)
+    }
+
+    fn render(&mut self, sample_rate: u32, data: &mut [f32], stereo: bool) {
+        self.prepare(sample_rate);
+        if self.controls.paused {
+            return;
+        }
+        let ratio = self.sample_rate as f64 * self.controls.settings.playback_rate as f64
+            / sample_rate as f64;
+        for out in data.chunks_exact_mut(if stereo { 2 } else { 1 }) {
+            let Some(frame) = self.next_frame(ratio) else {
+                break;
+            };
+            let amp = self.controls.amplifier();
+            let frame = self.controls.filter(frame * amp);
+            if stereo {
+                out[0] += frame.0;
+                out[1] += frame.1;
+            } else {
+                out[0] += frame.avg();
+            }
+            if self.controls.paused {
+                break;
+            }
+        }
+
+        let position = (self.index as f64 + self.phase).max(0.) / self.sample_rate as f64;
+        self.controls.store_position(position as f32);
+    }
+}
+
+impl Renderer for StreamingMusicRenderer {
+    fn alive(&self) -> bool {
+        self.controls.alive()
+    }
+
+    fn render_mono(&mut self, sample_rate: u32, data: &mut [f32]) {
+        self.render(sample_rate, data, false);
+    }
+
+    fn render_stereo(&mut self, sample_rate: u32, data: &mut [f32]) {
+        self.render(sample_rate, data, true);
+    }
+}
+
+impl Music {
+    /// Creates music that is decoded on a background thread while it plays, instead of being
+    /// decoded into an [`AudioClip`](crate::AudioClip) up front.
+    ///
+    /// Looping with a positive `loop_mix_time` needs the length of the track, which some formats
+    /// only know after decoding it; without a length the track restarts right after it ends.
+    pub(crate) fn new_streaming(
+        source: Box<dyn MediaSource>,
+        options: &DecodeOptions,
+        settings: MusicParams,
+        stats: Arc<StatsState>,
+    ) -> Result<(Music, StreamingMusicRenderer)> {
+        let decoder = StreamDecoder::open(source, options, &settings)?;
+        let sample_rate = decoder.sample_rate;
+        let (music, state, cons) = Self::channel(&settings);
+        let (prod, frames) = HeapRb::new(settings.stream_buffer_size).split();
+        let seek = Arc::<SeekRequest>::default();
+        thread::Builder::new()
+            .name("sasa-music-stream".to_owned())
+            .spawn({
+                let seek = Arc::clone(&seek);
+                let state = Weak::clone(&state);
+                move || decoder.run(prod, seek, state, stats)
+            })
+            .context("failed to spawn decoding thread")?;
+        let renderer = StreamingMusicRenderer {
+            controls: Controls::new(settings, state, cons),
+            frames,
+            seek,
+            generation: 0,
+            sample_rate,
+            window: [Frame::default(); 2],
+            filled: 0,
+            index: 0,
+            phase: 0.,
+            ended: false,
+        };
+        Ok((music, renderer))
+    }
+}
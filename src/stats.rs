@@ -0,0 +1,268 @@
+use crate::{
+    xrun::{XrunKind, XrunLog, LATE_CALLBACK_FACTOR},
+    LATENCY_RECORD_NUM,
+};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+/// Upper edges (exclusive, in milliseconds) of the buckets of
+/// [`AudioStats::interval_histogram`]. The last bucket holds everything above the last edge.
+pub const INTERVAL_HISTOGRAM_EDGES_MS: [f32; INTERVAL_HISTOGRAM_BUCKETS - 1] =
+    [1., 2., 5., 10., 20., 50., 100.];
+pub const INTERVAL_HISTOGRAM_BUCKETS: usize = 8;
+
+#[derive(Debug, Clone, Default)]
+pub struct AudioStats {
+    pub callbacks: u64,
+
+    /// Size of the buffers the device asked for, in frames.
+    pub last_buffer_frames: u32,
+    pub min_buffer_frames: u32,
+    pub max_buffer_frames: u32,
+
+    /// Output latency in seconds. Minimum and maximum cover the whole session, the rest only the
+    /// most recent measurements.
+    pub latency_min: f32,
+    pub latency_max: f32,
+    pub latency_mean: f32,
+    pub latency_p50: f32,
+    pub latency_p95: f32,
+    pub latency_p99: f32,
+
+    /// Deviation of the time between two callbacks from the duration of the previous buffer, in
+    /// seconds.
+    pub jitter_mean: f32,
+    pub jitter_max: f32,
+    /// Number of callback intervals in each bucket of [`INTERVAL_HISTOGRAM_EDGES_MS`].
+    pub interval_histogram: [u64; INTERVAL_HISTOGRAM_BUCKETS],
+
+    /// Time spent in the mixer relative to the duration of the rendered buffer. Values close to
+    /// 1 mean the buffer is too small for the device.
+    pub dsp_load: f32,
+    pub dsp_load_mean: f32,
+    pub dsp_load_max: f32,
+}
+
+#[derive(Clone, Copy, Default)]
+struct TimingData {
+    callbacks: u64,
+    last_frames: u32,
+    min_frames: u32,
+    max_frames: u32,
+    intervals: u64,
+    jitter_sum: f64,
+    jitter_max: f32,
+    interval_histogram: [u64; INTERVAL_HISTOGRAM_BUCKETS],
+    dsp_load: f32,
+    dsp_load_sum: f64,
+    dsp_load_max: f32,
+}
+
+#[derive(Clone, Copy)]
+struct LatencyData {
+    records: [f32; LATENCY_RECORD_NUM],
+    len: usize,
+    min: f32,
+    max: f32,
+}
+impl Default for LatencyData {
+    fn default() -> Self {
+        Self {
+            records: [0.; LATENCY_RECORD_NUM],
+            len: 0,
+            min: f32::INFINITY,
+            max: 0.,
+        }
+    }
+}
+
+/// Statistics shared between the audio thread and [`AudioManager`](crate::AudioManager).
+///
+/// The audio thread keeps its own copy and publishes it with `try_lock` after every callback, so
+/// it never blocks; a skipped publish is caught up on the next callback.
+#[derive(Default)]
+pub(crate) struct StatsState {
+    timing: Mutex<TimingData>,
+    latency: Mutex<LatencyData>,
+    generation: AtomicU32,
+    /// Set when a stream starts or resumes, so that the gap before its first callback isn't
+    /// taken for a callback interval.
+    restarted: AtomicBool,
+    pub xruns: XrunLog,
+}
+
+impl StatsState {
+    pub fn snapshot(&self) -> AudioStats {
+        let timing = *self.timing.lock().unwrap();
+        let latency = *self.latency.lock().unwrap();
+
+        let mut stats = AudioStats {
+            callbacks: timing.callbacks,
+            last_buffer_frames: timing.last_frames,
+            min_buffer_frames: timing.min_frames,
+            max_buffer_frames: timing.max_frames,
+            jitter_max: timing.jitter_max,
+            interval_histogram: timing.interval_histogram,
+            dsp_load: timing.dsp_load,
+            dsp_load_max: timing.dsp_load_max,
+            ..Default::default()
+        };
+        if timing.intervals != 0 {
+            stats.jitter_mean = (timing.jitter_sum / timing.intervals as f64) as f32;
+        }
+        if timing.callbacks != 0 {
+            stats.dsp_load_mean = (timing.dsp_load_sum / timing.callbacks as f64) as f32;
+        }
+        if latency.len != 0 {
+            let mut records = latency.records[..latency.len].to_vec();
+            records.sort_by(f32::total_cmp);
+            let percentile = |p: f32| records[((records.len() - 1) as f32 * p).round() as usize];
+            stats.latency_min = latency.min;
+            stats.latency_max = latency.max;
+            stats.latency_mean = records.iter().sum::<f32>() / records.len() as f32;
+            stats.latency_p50 = percentile(0.5);
+            stats.latency_p95 = percentile(0.95);
+            stats.latency_p99 = percentile(0.99);
+        }
+        stats
+    }
+
+    pub fn reset(&self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+        self.xruns.reset();
+    }
+
+    pub fn mark_restarted(&self) {
+        self.restarted.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Audio thread side of the callback timing statistics, fed by the mixer.
+pub(crate) struct StatsRecorder {
+    state: Arc<StatsState>,
+    generation: u32,
+    data: TimingData,
+    last_start: Option<Instant>,
+    last_period: f32,
+}
+
+impl StatsRecorder {
+    pub fn new(state: Arc<StatsState>) -> Self {
+        Self {
+            state,
+            generation: 0,
+            data: TimingData::default(),
+            last_start: None,
+            last_period: 0.,
+        }
+    }
+
+    /// Records a callback that started rendering `frames` frames at `start` and has just
+    /// finished.
+    pub fn push(&mut self, start: Instant, frames: usize, sample_rate: u32) {
+        let generation = self.state.generation.load(Ordering::Relaxed);
+        if generation != self.generation {
+            self.generation = generation;
+            self.data = TimingData::default();
+        }
+        if self.state.restarted.swap(false, Ordering::Relaxed) {
+            self.last_start = None;
+        }
+
+        let data = &mut self.data;
+        let frames = frames as u32;
+        data.last_frames = frames;
+        data.min_frames = if data.callbacks == 0 {
+            frames
+        } else {
+            data.min_frames.min(frames)
+        };
+        data.max_frames = data.max_frames.max(frames);
+        data.callbacks += 1;
+
+        if let Some(last_start) = self.last_start {
+            let interval = start.duration_since(last_start).as_secs_f32();
+            let jitter = (interval - self.last_period).abs();
+            data.intervals += 1;
+            data.jitter_sum += jitter as f64;
+            data.jitter_max = data.jitter_max.max(jitter);
+            let ms = interval * 1000.;
+            let bucket = INTERVAL_HISTOGRAM_EDGES_MS
+                .iter()
+                .position(|&edge| ms < edge)
+                .unwrap_or(INTERVAL_HISTOGRAM_BUCKETS - 1);
+            data.interval_histogram[bucket] += 1;
+
+            let expected = self.last_period * LATE_CALLBACK_FACTOR;
+            if interval > expected {
+                self.state.xruns.push(XrunKind::LateCallback {
+                    interval,
+                    expected: self.last_period,
+                });
+            }
+        }
+        let period = frames as f32 / sample_rate.max(1) as f32;
+        self.last_start = Some(start);
+        self.last_period = period;
+
+        if period > 0. {
+            let render_time = start.elapsed().as_secs_f32();
+            let load = render_time / period;
+            if load > 1. {
+                self.state.xruns.push(XrunKind::Overrun {
+                    render_time,
+                    period,
+                });
+            }
+            data.dsp_load = load;
+            data.dsp_load_sum += load as f64;
+            data.dsp_load_max = data.dsp_load_max.max(load);
+        }
+
+        if let Ok(mut shared) = self.state.timing.try_lock() {
+            *shared = *data;
+        }
+    }
+}
+
+/// Audio thread side of the latency statistics, fed by [`LatencyRecorder`](crate::LatencyRecorder).
+pub(crate) struct LatencyStatsRecorder {
+    state: Arc<StatsState>,
+    generation: u32,
+    data: LatencyData,
+}
+
+impl LatencyStatsRecorder {
+    pub fn new(state: Arc<StatsState>) -> Self {
+        Self {
+            state,
+            generation: 0,
+            data: LatencyData::default(),
+        }
+    }
+
+    /// Records `record` given the current window of the latency recorder.
+    pub fn push(&mut self, record: f32, records: &[f32; LATENCY_RECORD_NUM], len: usize) {
+        let generation = self.state.generation.load(Ordering::Relaxed);
+        if generation != self.generation {
+            self.generation = generation;
+            self.data = LatencyData::default();
+        }
+
+        let data = &mut self.data;
+        // the window of recent measurements is kept across resets, only the extremes start over
+        data.records = *records;
+        data.len = len;
+        data.min = data.min.min(record);
+        data.max = data.max.max(record);
+
+        if let Ok(mut shared) = self.state.latency.try_lock() {
+            *shared = *data;
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::io::{Result, Write};
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+
+/// Converts `sample` to a signed integer of `bits` bits, clamping it to full scale.
+pub(crate) fn quantize(sample: f32, bits: u32) -> i32 {
+    let max = (1i64 << (bits - 1)) - 1;
+    (sample.clamp(-1., 1.) as f64 * max as f64).round() as i32
+}
+
+/// Sample encoding of raw PCM and WAV output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcmFormat {
+    I16,
+    I24,
+    F32,
+}
+
+impl PcmFormat {
+    pub fn bytes_per_sample(self) -> usize {
+        match self {
+            Self::I16 => 2,
+            Self::I24 => 3,
+            Self::F32 => 4,
+        }
+    }
+
+    /// Appends `samples` to `out` as little-endian bytes, clamping integer output to full
+    /// scale.
+    pub(crate) fn encode(self, samples: &[f32], out: &mut Vec<u8>) {
+        match self {
+            Self::I16 => {
+                for sample in samples {
+                    let sample = quantize(*sample, 16) as i16;
+                    out.extend_from_slice(&sample.to_le_bytes());
+                }
+            }
+            Self::I24 => {
+                for sample in samples {
+                    let sample = quantize(*sample, 24);
+                    out.extend_from_slice(&sample.to_le_bytes()[..3]);
+                }
+            }
+            Self::F32 => {
+                for sample in samples {
+                    out.extend_from_slice(&sample.to_le_bytes());
+                }
+            }
+        }
+    }
+}
+
+/// Writes a WAV header for `data_len` bytes of samples. Without a length, the sizes are set to
+/// their maximum, which most readers treat as "until the end of the stream".
+pub(crate) fn write_header(
+    w: &mut impl Write,
+    sample_rate: u32,
+    channels: u16,
+    format: PcmFormat,
+    data_len: Option<u32>,
+) -> Result<()> {
+    let (tag, fmt_len) = match format {
+        PcmFormat::F32 => (WAVE_FORMAT_IEEE_FLOAT, 18u32),
+        PcmFormat::I16 | PcmFormat::I24 => (WAVE_FORMAT_PCM, 16),
+    };
+    let bytes_per_sample = format.bytes_per_sample() as u16;
+    let block_align = channels * bytes_per_sample;
+    let riff_len = data_len.map_or(u32::MAX, |len| (4 + 8 + fmt_len + 8).saturating_add(len));
+
+    w.write_all(b"RIFF")?;
+    w.write_all(&riff_len.to_le_bytes())?;
+    w.write_all(b"WAVE")?;
+
+    w.write_all(b"fmt ")?;
+    w.write_all(&fmt_len.to_le_bytes())?;
+    w.write_all(&tag.to_le_bytes())?;
+    w.write_all(&channels.to_le_bytes())?;
+    w.write_all(&sample_rate.to_le_bytes())?;
+    w.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    w.write_all(&block_align.to_le_bytes())?;
+    w.write_all(&(bytes_per_sample * 8).to_le_bytes())?;
+    if fmt_len == 18 {
+        w.write_all(&0u16.to_le_bytes())?;
+    }
+
+    w.write_all(b"data")?;
+    w.write_all(&data_len.unwrap_or(u32::MAX).to_le_bytes())?;
+    Ok(())
+}
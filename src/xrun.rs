@@ -0,0 +1,105 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+/// A callback is considered late when it arrives this many times the previous buffer duration
+/// after the previous one.
+pub(crate) const LATE_CALLBACK_FACTOR: f32 = 1.5;
+
+const RECENT_XRUNS: usize = 64;
+
+#[derive(Debug, Clone)]
+pub enum XrunKind {
+    /// The mixer took longer to render a buffer than the buffer lasts.
+    Overrun { render_time: f32, period: f32 },
+    /// The callback came later than expected, which usually means the device ran out of data.
+    LateCallback { interval: f32, expected: f32 },
+    /// The backend or a music stream reported an error. Fatal ones also mark the backend broken,
+    /// see [`AudioManager::consume_broken`](crate::AudioManager::consume_broken).
+    StreamError(String),
+    /// Something didn't go as requested but was worked around, e.g. a missing device replaced by
+    /// the default one.
+    Warning(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct XrunEvent {
+    pub time: Instant,
+    pub kind: XrunKind,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct XrunReport {
+    pub overruns: u64,
+    pub late_callbacks: u64,
+    pub stream_errors: u64,
+    pub warnings: u64,
+    /// The most recent events, oldest first.
+    pub recent: Vec<XrunEvent>,
+}
+
+pub(crate) struct XrunLog {
+    overruns: AtomicU64,
+    late_callbacks: AtomicU64,
+    stream_errors: AtomicU64,
+    warnings: AtomicU64,
+    recent: Mutex<VecDeque<XrunEvent>>,
+}
+
+impl Default for XrunLog {
+    fn default() -> Self {
+        Self {
+            overruns: AtomicU64::default(),
+            late_callbacks: AtomicU64::default(),
+            stream_errors: AtomicU64::default(),
+            warnings: AtomicU64::default(),
+            recent: Mutex::new(VecDeque::with_capacity(RECENT_XRUNS)),
+        }
+    }
+}
+
+impl XrunLog {
+    /// Records an event. Counters are always updated, but the event is dropped from the log
+    /// rather than blocking if the log is being read at the same time.
+    pub fn push(&self, kind: XrunKind) {
+        let counter = match kind {
+            XrunKind::Overrun { .. } => &self.overruns,
+            XrunKind::LateCallback { .. } => &self.late_callbacks,
+            XrunKind::StreamError(_) => &self.stream_errors,
+            XrunKind::Warning(_) => &self.warnings,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut recent) = self.recent.try_lock() {
+            if recent.len() == RECENT_XRUNS {
+                recent.pop_front();
+            }
+            recent.push_back(XrunEvent {
+                time: Instant::now(),
+                kind,
+            });
+        }
+    }
+
+    pub fn report(&self) -> XrunReport {
+        XrunReport {
+            overruns: self.overruns.load(Ordering::Relaxed),
+            late_callbacks: self.late_callbacks.load(Ordering::Relaxed),
+            stream_errors: self.stream_errors.load(Ordering::Relaxed),
+            warnings: self.warnings.load(Ordering::Relaxed),
+            recent: self.recent.lock().unwrap().iter().cloned().collect(),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.overruns.store(0, Ordering::Relaxed);
+        self.late_callbacks.store(0, Ordering::Relaxed);
+        self.stream_errors.store(0, Ordering::Relaxed);
+        self.warnings.store(0, Ordering::Relaxed);
+        self.recent.lock().unwrap().clear();
+    }
+}
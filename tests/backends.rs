@@ -0,0 +1,100 @@
+use anyhow::Result;
+use sasa::{
+    backend::{
+        null::{NullBackend, NullSettings},
+        writer::{Pacing, WriterBackend, WriterSettings},
+        BackendSetup,
+    },
+    AudioManager, Backend, BackendState, XrunKind, INTERVAL_HISTOGRAM_BUCKETS,
+};
+use std::{
+    io::{self, Write},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[test]
+fn start_before_setup_fails() {
+    assert!(NullBackend::new(NullSettings::default()).start().is_err());
+    assert!(WriterBackend::new(Vec::new(), WriterSettings::default())
+        .start()
+        .is_err());
+}
+
+/// A backend implementing only the required methods.
+struct MinimalBackend;
+
+impl Backend for MinimalBackend {
+    fn setup(&mut self, _setup: BackendSetup) -> Result<()> {
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn consume_broken(&self) -> bool {
+        false
+    }
+}
+
+#[test]
+fn default_methods() {
+    let mut manager = AudioManager::new(MinimalBackend).unwrap();
+    assert_eq!(manager.state(), BackendState::Running);
+    assert!(manager.stop().is_err());
+    assert!(manager.suspend().is_err());
+    assert!(manager.resume().is_err());
+    assert!(!manager.consume_broken());
+}
+
+#[test]
+fn restart_gap_is_not_a_callback_interval() {
+    let mut manager = AudioManager::new(NullBackend::new(NullSettings::default())).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    manager.suspend().unwrap();
+    thread::sleep(Duration::from_millis(300));
+    manager.resume().unwrap();
+    thread::sleep(Duration::from_millis(50));
+
+    let stats = manager.stats();
+    assert!(stats.jitter_max < 0.2, "{stats:?}");
+    assert_eq!(stats.interval_histogram[INTERVAL_HISTOGRAM_BUCKETS - 1], 0);
+    assert!(!manager.xruns().recent.iter().any(|it| matches!(
+        it.kind,
+        XrunKind::LateCallback { interval, .. } if interval >= 0.2
+    )));
+}
+
+struct BrokenPipe;
+
+impl Write for BrokenPipe {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn write_errors_are_logged() {
+    let manager = AudioManager::new(WriterBackend::new(
+        BrokenPipe,
+        WriterSettings {
+            wav_header: false,
+            pacing: Pacing::Unpaced,
+            ..WriterSettings::default()
+        },
+    ))
+    .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !manager.consume_broken() {
+        assert!(Instant::now() < deadline, "write error is not reported");
+        thread::sleep(Duration::from_millis(1));
+    }
+    let xruns = manager.xruns();
+    assert_eq!(xruns.stream_errors, 1);
+    assert!(matches!(&xruns.recent[..], [it] if matches!(it.kind, XrunKind::StreamError(_))));
+}
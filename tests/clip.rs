@@ -0,0 +1,381 @@
+use sasa::{
+    AudioClip, DecodeOptions, DecodePolicy, FormatHint, Frame, Interpolation, PcmFormat,
+    ResampleQuality, StreamEnd,
+};
+use std::{fs, io::Cursor, path::PathBuf};
+
+fn wav_data(frames: usize, sample_rate: u32) -> Vec<u8> {
+    let clip = AudioClip::from_raw(vec![Frame(0.5, -0.5); frames], sample_rate);
+    let mut data = Vec::new();
+    clip.write_wav(&mut data, PcmFormat::I16).unwrap();
+    data
+}
+
+/// A second of a tone in the left channel and a slower one in the right, ending at full scale.
+fn tone(sample_rate: u32) -> AudioClip {
+    let frames = (0..sample_rate)
+        .map(|i| {
+            let t = i as f32 / sample_rate as f32 * std::f32::consts::TAU;
+            Frame((t * 440.).sin(), (t * 110.).cos())
+        })
+        .chain([Frame(1., -1.)])
+        .collect();
+    AudioClip::from_raw(frames, sample_rate)
+}
+
+/// A file in the temporary directory, removed when dropped.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str, data: &[u8]) -> Self {
+        let path = std::env::temp_dir().join(format!("sasa-{}-{name}", std::process::id()));
+        fs::write(&path, data).unwrap();
+        Self(path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+#[test]
+fn probe_variants() {
+    let data = wav_data(22050, 44100);
+    let file = TempFile::new("probe.wav", &data);
+    let infos = [
+        AudioClip::probe(data.clone()).unwrap(),
+        AudioClip::probe_path(&file.0).unwrap(),
+        AudioClip::probe_source(
+            Cursor::new(data),
+            &FormatHint {
+                extension: Some("wav".to_owned()),
+                mime_type: None,
+            },
+        )
+        .unwrap(),
+    ];
+    for info in infos {
+        assert_eq!(info.track.sample_rate, Some(44100));
+        assert_eq!(info.track.channels, Some(2));
+        assert_eq!(info.track.duration, Some(0.5));
+    }
+    assert!(AudioClip::probe_path(file.0.with_extension("missing")).is_err());
+}
+
+#[test]
+fn tracks_variants() {
+    let data = wav_data(4800, 48000);
+    let file = TempFile::new("tracks.wav", &data);
+    let lists = [
+        AudioClip::tracks(data.clone()).unwrap(),
+        AudioClip::tracks_path(&file.0).unwrap(),
+        AudioClip::tracks_source(Cursor::new(data), &FormatHint::default()).unwrap(),
+    ];
+    for tracks in lists {
+        assert!(matches!(&tracks[..], [track] if track.is_default));
+        assert_eq!(tracks[0].sample_rate, Some(48000));
+        assert_eq!(tracks[0].duration, Some(0.1));
+    }
+}
+
+/// Writes the lowest `bits` bits of `value` after the `len` bits already in `data`.
+fn push_bits(data: &mut [u8], len: &mut usize, value: u32, bits: u32) {
+    for i in (0..bits).rev() {
+        if value >> i & 1 != 0 {
+            data[*len / 8] |= 0x80 >> (*len % 8);
+        }
+        *len += 1;
+    }
+}
+
+/// Silent mono mp3 frames at 44.1 kHz and 128 kbps, 1152 samples each. The first one reuses a
+/// byte of a previous frame through the bit reservoir, like the first frame of a stream cut from
+/// a longer one, and its first granule claims more bits than the reservoir holds.
+fn mp3_data(frames: usize) -> Vec<u8> {
+    const FRAME_SIZE: usize = 144 * 128000 / 44100;
+    let mut data = Vec::new();
+    for i in 0..frames {
+        let mut frame = vec![0; FRAME_SIZE];
+        // MPEG-1 layer III without CRC, 128 kbps, 44.1 kHz, mono
+        frame[..4].copy_from_slice(&[0xff, 0xfb, 0x90, 0xc0]);
+        if i == 0 {
+            let mut len = 32;
+            // main_data_begin
+            push_bits(&mut frame, &mut len, 1, 9);
+            // private bits and scfsi
+            push_bits(&mut frame, &mut len, 0, 9);
+            // part2_3_length of the first granule
+            push_bits(&mut frame, &mut len, 8 + 8 * FRAME_SIZE as u32, 12);
+        }
+        data.extend(frame);
+    }
+    data
+}
+
+#[test]
+fn skips_missing_mp3_reservoir() {
+    let clip = AudioClip::new(mp3_data(8)).unwrap();
+    assert_eq!(clip.sample_rate(), 44100);
+    assert_eq!(clip.frame_count(), 7 * 1152);
+    assert!(clip.frames().iter().all(|it| *it == Frame::default()));
+
+    for policy in [
+        DecodePolicy::Strict,
+        DecodePolicy::SkipCorrupt,
+        DecodePolicy::BestEffort,
+    ] {
+        let options = DecodeOptions {
+            policy,
+            ..DecodeOptions::default()
+        };
+        let (frames, _, report) =
+            AudioClip::decode_with(Cursor::new(mp3_data(8)), &options).unwrap();
+        assert_eq!(frames.len(), 7 * 1152);
+        assert_eq!(report.packets, 7);
+        assert_eq!(report.skipped, 1);
+        assert_eq!(report.skipped_packets[0].time, 0.);
+        assert!(report.skipped_packets[0].reason.contains("main_data"));
+    }
+}
+
+#[test]
+fn strict_decoding_fails_on_damaged_streams() {
+    // a frame with a valid header but invalid side info
+    let mut data = mp3_data(8);
+    let damaged = 3 * (144 * 128000 / 44100);
+    data[damaged + 6] = 0xff;
+    data[damaged + 7] = 0xff;
+    assert!(AudioClip::new(data.clone()).is_err());
+
+    let options = DecodeOptions {
+        policy: DecodePolicy::SkipCorrupt,
+        ..DecodeOptions::default()
+    };
+    let (_, _, report) = AudioClip::decode_with(Cursor::new(data), &options).unwrap();
+    // the first frame and the damaged one
+    assert_eq!(report.skipped, 2);
+    assert!(!report.skipped_packets[1].reason.contains("main_data"));
+}
+
+#[test]
+fn reports_truncated_streams() {
+    let data = wav_data(4800, 48000);
+    let (frames, _, report) =
+        AudioClip::decode_with(Cursor::new(data.clone()), &DecodeOptions::default()).unwrap();
+    assert_eq!(frames.len(), 4800);
+    assert_eq!(report.end, StreamEnd::Finished);
+
+    // cut the last 800 frames of 16-bit stereo
+    let cut = &data[..data.len() - 800 * 4];
+    for policy in [
+        DecodePolicy::Strict,
+        DecodePolicy::SkipCorrupt,
+        DecodePolicy::BestEffort,
+    ] {
+        let options = DecodeOptions {
+            policy,
+            ..DecodeOptions::default()
+        };
+        let (frames, _, report) =
+            AudioClip::decode_with(Cursor::new(cut.to_vec()), &options).unwrap();
+        assert_eq!(frames.len(), 4000);
+        assert_eq!(
+            report.end,
+            StreamEnd::Truncated {
+                expected: 4800,
+                decoded: 4000
+            }
+        );
+    }
+}
+
+#[test]
+fn wav_round_trip() {
+    let empty = AudioClip::from_raw(Vec::new(), 44100);
+    for original in [tone(44100), empty] {
+        for (format, tolerance) in [
+            // rounding, plus the decoder scaling by 2^(bits - 1) instead of 2^(bits - 1) - 1
+            (PcmFormat::I16, 2. / 32768.),
+            (PcmFormat::I24, 2. / 8388608.),
+            (PcmFormat::F32, 0.),
+        ] {
+            let mut data = Vec::new();
+            original.write_wav(&mut data, format).unwrap();
+            let decoded = AudioClip::new(data).unwrap();
+            assert_eq!(decoded.sample_rate(), 44100);
+            assert_eq!(decoded.frame_count(), original.frame_count());
+            for (a, b) in original.frames().iter().zip(decoded.frames()) {
+                assert!(
+                    (a.0 - b.0).abs() <= tolerance && (a.1 - b.1).abs() <= tolerance,
+                    "{a:?} != {b:?}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn resample_length() {
+    let clip = tone(44100);
+    for (sample_rate, len) in [(48000, 48001), (22050, 22051), (96000, 96002)] {
+        let resampled = clip.resample(sample_rate, ResampleQuality::Fast);
+        assert_eq!(resampled.sample_rate(), sample_rate);
+        assert_eq!(resampled.frame_count(), len);
+        assert!((resampled.length() - clip.length()).abs() < 1e-4);
+    }
+}
+
+#[test]
+fn resample_keeps_unity_gain() {
+    let clip = AudioClip::from_raw(vec![Frame(0.5, -0.25); 4410], 44100)
+        .with_interpolation(Interpolation::CubicHermite)
+        .with_gain(0.8);
+    for quality in [
+        ResampleQuality::Fast,
+        ResampleQuality::Medium,
+        ResampleQuality::High,
+    ] {
+        let resampled = clip.resample(48000, quality);
+        assert_eq!(resampled.gain(), 0.8);
+        assert_eq!(resampled.interpolation(), Interpolation::CubicHermite);
+        // away from the edges, where the filter reaches into the silence around the clip
+        for frame in &resampled.frames()[100..4700] {
+            assert!((frame.0 - 0.5).abs() < 1e-3 && (frame.1 + 0.25).abs() < 1e-3);
+        }
+    }
+}
+
+#[test]
+fn resample_to_the_same_rate() {
+    let clip = tone(44100);
+    let resampled = clip.resample(44100, ResampleQuality::High);
+    assert_eq!(resampled.frames(), clip.frames());
+    assert_eq!(resampled.frames().as_ptr(), clip.frames().as_ptr());
+}
+
+/// A clip whose left channel is the index of each frame.
+fn indices(len: usize, sample_rate: u32) -> AudioClip {
+    let frames = (0..len).map(|i| Frame(i as f32, 0.)).collect();
+    AudioClip::from_raw(frames, sample_rate)
+}
+
+#[test]
+fn slice_is_a_view() {
+    let clip = indices(1000, 1000);
+    let slice = clip.slice(0.1, 0.3);
+    assert_eq!(slice.frame_count(), 200);
+    assert_eq!(slice.length(), 0.2);
+    assert_eq!(slice.sample_rate(), 1000);
+    assert_eq!(slice.frames()[0], Frame(100., 0.));
+    assert_eq!(slice.frames()[199], Frame(299., 0.));
+    assert_eq!(slice.frames().as_ptr(), clip.frames()[100..].as_ptr());
+
+    // positions are relative to the start of the slice
+    assert_eq!(slice.sample(0.), Some(Frame(100., 0.)));
+    assert_eq!(slice.sample(0.0505).map(|it| it.0.round()), Some(151.));
+    assert_eq!(slice.sample(0.199), Some(Frame(299., 0.)));
+    assert_eq!(slice.sample(0.2), None);
+    // gain and interpolation carry over
+    assert_eq!(
+        clip.with_gain(0.5).slice(0.1, 0.3).sample(0.),
+        Some(Frame(50., 0.))
+    );
+}
+
+#[test]
+fn nested_slices() {
+    let clip = indices(1000, 1000);
+    let slice = clip.slice(0.1, 0.9).slice(0.2, 0.5).slice(0.05, 1.);
+    assert_eq!(slice.frame_count(), 250);
+    assert_eq!(slice.frames()[0], Frame(350., 0.));
+    assert_eq!(slice.frames()[249], Frame(599., 0.));
+}
+
+#[test]
+fn slice_bounds() {
+    let clip = indices(1000, 1000);
+    let clamped = clip.slice(-1., 2.);
+    assert_eq!(clamped.frame_count(), 1000);
+    assert_eq!(clamped.frames(), clip.frames());
+
+    let tail = clip.slice(0.9, 5.);
+    assert_eq!(tail.frame_count(), 100);
+    assert_eq!(tail.frames()[99], Frame(999., 0.));
+
+    for empty in [
+        clip.slice(0.5, 0.2),
+        clip.slice(2., 3.),
+        clip.slice(0.5, 0.5),
+    ] {
+        assert_eq!(empty.frame_count(), 0);
+        assert_eq!(empty.length(), 0.);
+        assert!(empty.frames().is_empty());
+        assert_eq!(empty.sample(0.), None);
+    }
+}
+
+#[test]
+fn slice_processing() {
+    // the viewed part is much quieter than the rest
+    let frames = tone(48000)
+        .frames()
+        .iter()
+        .enumerate()
+        .map(|(i, it)| match i {
+            12000..=35999 => *it * 0.1,
+            _ => *it,
+        })
+        .collect();
+    let clip = AudioClip::from_raw(frames, 48000);
+    let slice = clip.slice(0.25, 0.75);
+
+    let resampled = slice.resample(24000, ResampleQuality::Medium);
+    assert_eq!(resampled.frame_count(), 12000);
+    let expected = AudioClip::from_raw(slice.frames().to_vec(), 48000)
+        .resample(24000, ResampleQuality::Medium);
+    assert_eq!(resampled.frames(), expected.frames());
+
+    let normalized = slice.normalize(-20.);
+    assert_eq!(normalized.frame_count(), 24000);
+    let gain = normalized.frames()[1000].0 / slice.frames()[1000].0;
+    for (a, b) in slice.frames().iter().zip(normalized.frames()) {
+        assert!((a.0 * gain - b.0).abs() < 1e-5 && (a.1 * gain - b.1).abs() < 1e-5);
+    }
+    let loudness = normalized.loudness().integrated.unwrap();
+    assert!((loudness + 20.).abs() < 0.01);
+}
+
+#[test]
+fn normalize() {
+    let clip = tone(48000).with_gain(0.5);
+    let before = clip.integrated_loudness().unwrap();
+    let normalized = clip.normalize(-23.);
+    assert!((normalized.integrated_loudness().unwrap() + 23.).abs() < 0.01);
+    assert_eq!(normalized.gain(), 0.5);
+
+    let gain = clip.gain_to(-23.).unwrap();
+    assert!((gain - 10f32.powf((-23. - before) / 20.)).abs() < 1e-6);
+    for (a, b) in clip.frames().iter().zip(normalized.frames()) {
+        assert!((a.0 * gain - b.0).abs() < 1e-6 && (a.1 * gain - b.1).abs() < 1e-6);
+    }
+
+    // too short to be measured
+    let short = clip.slice(0., 0.3);
+    assert_eq!(short.gain_to(-23.), None);
+    assert_eq!(short.normalize(-23.).frames(), short.frames());
+}
+
+#[test]
+fn with_gain() {
+    let clip = tone(48000);
+    let louder = clip.with_gain(2.);
+    assert_eq!(louder.gain(), 2.);
+    assert_eq!(louder.frames().as_ptr(), clip.frames().as_ptr());
+    assert_eq!(clip.gain(), 1.);
+    assert_eq!(louder.sample(0.1), clip.sample(0.1).map(|it| it * 2.));
+    // measurements ignore the gain
+    assert_eq!(louder.integrated_loudness(), clip.integrated_loudness());
+    assert_eq!(louder.loudness().peak, clip.loudness().peak);
+}
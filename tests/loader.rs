@@ -0,0 +1,69 @@
+use sasa::{
+    AudioClip, ClipLoader, DecodePolicy, FormatHint, Frame, LoadError, LoaderSettings, PcmFormat,
+};
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use symphonia::core::io::MediaSource;
+
+fn wav(frames: usize) -> Cursor<Vec<u8>> {
+    let clip = AudioClip::from_raw(vec![Frame(0.25, -0.25); frames], 48000);
+    let mut data = Vec::new();
+    clip.write_wav(&mut data, PcmFormat::I16).unwrap();
+    Cursor::new(data)
+}
+
+fn loader() -> ClipLoader {
+    ClipLoader::new(LoaderSettings {
+        workers: 1,
+        policy: DecodePolicy::Strict,
+    })
+    .unwrap()
+}
+
+#[test]
+fn returns_the_report() {
+    let loaded = loader()
+        .load_source(wav(4800), FormatHint::default())
+        .wait()
+        .unwrap();
+    assert_eq!(loaded.clip.frame_count(), 4800);
+    assert!(loaded.report.packets > 0);
+    assert_eq!(loaded.report.skipped, 0);
+}
+
+/// A source whose reads panic, standing in for a buggy decoder.
+struct Panicking;
+
+impl Read for Panicking {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        panic!("read panicked");
+    }
+}
+
+impl Seek for Panicking {
+    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
+        Ok(0)
+    }
+}
+
+impl MediaSource for Panicking {
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+#[test]
+fn panics_resolve_the_handle() {
+    let loader = loader();
+    let result = loader.load_source(Panicking, FormatHint::default()).wait();
+    assert!(matches!(result, Err(LoadError::Panicked(message)) if message == "read panicked"));
+
+    // the worker keeps loading
+    assert!(loader
+        .load_source(wav(100), FormatHint::default())
+        .wait()
+        .is_ok());
+}
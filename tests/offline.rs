@@ -0,0 +1,85 @@
+use sasa::{
+    backend::manual::{ManualBackend, ManualSettings},
+    AudioClip, AudioManager, Frame, MusicParams, PlaySfxParams, Timeline,
+};
+use std::cell::RefCell;
+
+const SAMPLE_RATE: u32 = 48000;
+
+/// A clip of a tone, so that resampling and interpolation have something to work on.
+fn tone(len: usize, sample_rate: u32) -> AudioClip {
+    let frames = (0..len)
+        .map(|i| {
+            let t = i as f32 / sample_rate as f32 * std::f32::consts::TAU;
+            Frame((t * 440.).sin() * 0.5, (t * 220.).sin() * 0.5)
+        })
+        .collect();
+    AudioClip::from_raw(frames, sample_rate)
+}
+
+/// Renders a second of a session that starts music, plays an sfx over it and pauses the music.
+fn session() -> Vec<Frame> {
+    let mut manager = AudioManager::new_offline().unwrap();
+    let music = RefCell::new(
+        manager
+            .create_music(tone(44100, 44100), MusicParams::default())
+            .unwrap(),
+    );
+    let sfx = RefCell::new(manager.create_sfx(tone(4800, 32000), None).unwrap());
+    let mut timeline = Timeline::new();
+    timeline
+        .at(0.1, || music.borrow_mut().play())
+        .at(0.25, || sfx.borrow_mut().play(PlaySfxParams::default()))
+        .at(0.5, || music.borrow_mut().pause());
+    let clip = manager.render_offline(1., SAMPLE_RATE, timeline).unwrap();
+    clip.frames().to_vec()
+}
+
+#[test]
+fn renders_are_identical() {
+    let first = session();
+    assert_eq!(first.len(), SAMPLE_RATE as usize);
+    assert!(first.iter().any(|it| *it != Frame::default()));
+    // bit-identical, not just close
+    let bits = |frames: &[Frame]| {
+        frames
+            .iter()
+            .flat_map(|it| [it.0.to_bits(), it.1.to_bits()])
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(bits(&first), bits(&session()));
+}
+
+#[test]
+fn actions_run_at_their_frame() {
+    let mut manager = AudioManager::new_offline().unwrap();
+    let music = RefCell::new(
+        manager
+            .create_music(
+                AudioClip::from_raw(vec![Frame(0.5, 0.5); 48000], SAMPLE_RATE),
+                MusicParams::default(),
+            )
+            .unwrap(),
+    );
+    let mut timeline = Timeline::new();
+    // added out of order on purpose
+    timeline
+        .at(0.3, || music.borrow_mut().pause())
+        .at(0.1, || music.borrow_mut().play())
+        .at(2., || panic!("actions after the end are dropped"));
+    let clip = manager.render_offline(0.5, SAMPLE_RATE, timeline).unwrap();
+
+    let frames = clip.frames();
+    assert_eq!(frames.len(), 24000);
+    assert!(frames[..4800].iter().all(|it| *it == Frame::default()));
+    assert!(frames[4800..14400].iter().all(|it| *it == Frame(0.5, 0.5)));
+    assert!(frames[14400..].iter().all(|it| *it == Frame::default()));
+}
+
+#[test]
+fn requires_an_offline_manager() {
+    let mut manager = AudioManager::new(ManualBackend::new(ManualSettings::default())).unwrap();
+    assert!(manager
+        .render_offline(0.1, SAMPLE_RATE, Timeline::new())
+        .is_err());
+}
@@ -0,0 +1,434 @@
+use sasa::{
+    backend::manual::{ManualBackend, ManualHandle, ManualSettings},
+    AudioClip, AudioManager, DecodeOptions, Frame, MusicParams, PcmFormat, PlaySfxParams,
+    ResampleQuality, Timeline, TrackSelector, Upmix,
+};
+use std::{io::Cursor, thread, time::Duration};
+
+const SAMPLE_RATE: u32 = 48000;
+
+fn manager() -> (AudioManager, ManualHandle) {
+    let backend = ManualBackend::new(ManualSettings {
+        sample_rate: SAMPLE_RATE,
+        ..ManualSettings::default()
+    });
+    let handle = backend.handle();
+    (AudioManager::new(backend).unwrap(), handle)
+}
+
+fn constant(frame: Frame, len: usize) -> AudioClip {
+    AudioClip::from_raw(vec![frame; len], SAMPLE_RATE)
+}
+
+/// A clip whose left channel is its own position in seconds.
+fn ramp(len: usize) -> AudioClip {
+    let frames = (0..len)
+        .map(|i| Frame(i as f32 / SAMPLE_RATE as f32, 0.))
+        .collect();
+    AudioClip::from_raw(frames, SAMPLE_RATE)
+}
+
+fn wav(clip: &AudioClip) -> Cursor<Vec<u8>> {
+    let mut data = Vec::new();
+    clip.write_wav(&mut data, PcmFormat::F32).unwrap();
+    Cursor::new(data)
+}
+
+fn assert_silent(frames: &[Frame]) {
+    assert!(frames.iter().all(|it| *it == Frame::default()));
+}
+
+#[test]
+fn music_starts_paused() {
+    let (mut manager, handle) = manager();
+    let music = manager
+        .create_music(constant(Frame(0.5, -0.5), 4800), MusicParams::default())
+        .unwrap();
+    assert_silent(&handle.render(1024));
+    assert_eq!(music.position(), 0.);
+}
+
+#[test]
+fn music_play_and_pause() {
+    let (mut manager, handle) = manager();
+    let mut music = manager
+        .create_music(constant(Frame(0.5, -0.5), 48000), MusicParams::default())
+        .unwrap();
+
+    music.play().unwrap();
+    let frames = handle.render(1024);
+    assert!(frames.iter().all(|it| *it == Frame(0.5, -0.5)));
+    assert!(!music.paused());
+    assert!((music.position() - 1024. / SAMPLE_RATE as f32).abs() < 1e-6);
+
+    music.pause().unwrap();
+    assert_silent(&handle.render(1024));
+    assert!(music.paused());
+    let position = music.position();
+
+    music.play().unwrap();
+    handle.render(512);
+    assert!((music.position() - position - 512. / SAMPLE_RATE as f32).abs() < 1e-6);
+}
+
+#[test]
+fn music_seek() {
+    let (mut manager, handle) = manager();
+    let mut music = manager
+        .create_music(ramp(48000), MusicParams::default())
+        .unwrap();
+
+    music.seek_to(0.5).unwrap();
+    music.play().unwrap();
+    let frames = handle.render(512);
+    assert!((frames[0].0 - 0.5).abs() < 1e-4);
+    assert!((frames[511].0 - (0.5 + 511. / SAMPLE_RATE as f32)).abs() < 1e-4);
+
+    music.seek_to(0.25).unwrap();
+    let frames = handle.render(512);
+    assert!((frames[0].0 - 0.25).abs() < 1e-4);
+}
+
+#[test]
+fn music_amplifier_and_playback_rate() {
+    let (mut manager, handle) = manager();
+    let mut music = manager
+        .create_music(
+            ramp(48000),
+            MusicParams {
+                amplifier: 0.5,
+                playback_rate: 2.,
+                ..MusicParams::default()
+            },
+        )
+        .unwrap();
+
+    music.play().unwrap();
+    let frames = handle.render(512);
+    assert!((frames[100].0 - 0.5 * 200. / SAMPLE_RATE as f32).abs() < 1e-5);
+    assert!((music.position() - 1024. / SAMPLE_RATE as f32).abs() < 1e-6);
+}
+
+#[test]
+fn music_end_of_clip() {
+    let (mut manager, handle) = manager();
+    let mut music = manager
+        .create_music(constant(Frame(0.5, 0.5), 1000), MusicParams::default())
+        .unwrap();
+
+    music.play().unwrap();
+    let frames = handle.render(2048);
+    assert!(frames[..1000].iter().all(|it| *it == Frame(0.5, 0.5)));
+    assert_silent(&frames[1000..]);
+    assert!(!music.paused());
+    assert!((music.position() - 1000. / SAMPLE_RATE as f32).abs() < 1e-6);
+
+    // rendering on keeps silence until the music is played again
+    assert_silent(&handle.render(1024));
+}
+
+#[test]
+fn streaming_music_end_of_track() {
+    let (mut manager, handle) = manager();
+    let mut music = manager
+        .create_streaming_music(
+            wav(&constant(Frame(0.5, 0.5), 1000)),
+            MusicParams::default(),
+        )
+        .unwrap();
+    // let the decoding thread fill the buffer
+    thread::sleep(Duration::from_millis(100));
+
+    music.play().unwrap();
+    let frames = handle.render(2048);
+    assert!(frames[..1000].iter().all(|it| *it == Frame(0.5, 0.5)));
+    assert_silent(&frames[1000..]);
+    // same as music played from a clip
+    assert!(!music.paused());
+    assert!((music.position() - 1000. / SAMPLE_RATE as f32).abs() < 1e-6);
+}
+
+#[test]
+fn streaming_music_decode_options() {
+    let (mut manager, _handle) = manager();
+    let options = DecodeOptions {
+        track: TrackSelector::Id(42),
+        ..DecodeOptions::default()
+    };
+    assert!(manager
+        .create_streaming_music_with(wav(&ramp(1000)), &options, MusicParams::default())
+        .is_err());
+}
+
+/// A clip at half the output rate with a single impulse. Played as it is, linear interpolation
+/// leaves the output silent more than a frame away from the impulse; converted beforehand, the
+/// sinc filter rings around it.
+fn impulse() -> AudioClip {
+    let mut frames = vec![Frame::default(); 200];
+    frames[100] = Frame(1., 1.);
+    AudioClip::from_raw(frames, SAMPLE_RATE / 2)
+}
+
+fn is_resampled(frames: &[Frame]) -> bool {
+    frames[197].0.abs() > 0.01
+}
+
+#[test]
+fn auto_resample_before_the_first_callback() {
+    for quality in [None, Some(ResampleQuality::Medium)] {
+        let (mut manager, handle) = manager();
+        manager.set_auto_resample(quality);
+        let mut music = manager
+            .create_music(impulse(), MusicParams::default())
+            .unwrap();
+        music.play().unwrap();
+        assert_eq!(is_resampled(&handle.render(400)), quality.is_some());
+    }
+}
+
+#[test]
+fn auto_resample_offline() {
+    let mut manager = AudioManager::new_offline().unwrap();
+    manager.set_auto_resample(Some(ResampleQuality::Medium));
+    let mut music = manager
+        .create_music(impulse(), MusicParams::default())
+        .unwrap();
+    music.play().unwrap();
+    let clip = manager
+        .render_offline(0.01, SAMPLE_RATE, Timeline::new())
+        .unwrap();
+    assert!(is_resampled(clip.frames()));
+
+    // later clips follow the rate of the last render, which now matches theirs
+    manager
+        .render_offline(0., SAMPLE_RATE / 2, Timeline::new())
+        .unwrap();
+    let mut music = manager
+        .create_music(impulse(), MusicParams::default())
+        .unwrap();
+    music.play().unwrap();
+    let clip = manager
+        .render_offline(0.01, SAMPLE_RATE / 2, Timeline::new())
+        .unwrap();
+    assert!((clip.frames()[100].0 - 1.).abs() < 1e-4);
+    assert_silent(&clip.frames()[..99]);
+}
+
+#[test]
+fn auto_normalize() {
+    let (mut manager, handle) = manager();
+    manager.set_auto_normalize(Some(-23.));
+    let frames = (0..SAMPLE_RATE)
+        .map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            Frame((t * 1000. * std::f32::consts::TAU).sin() * 0.5, 0.)
+        })
+        .collect();
+    let clip = AudioClip::from_raw(frames, SAMPLE_RATE);
+    let gain = clip.gain_to(-23.).unwrap();
+    let mut music = manager
+        .create_music(clip.clone(), MusicParams::default())
+        .unwrap();
+    music.play().unwrap();
+    // the position of music drifts a little, so tones are compared loosely
+    for (a, b) in clip.frames().iter().zip(handle.render(4800)) {
+        assert!((a.0 * gain - b.0).abs() < 1e-3);
+    }
+}
+
+#[test]
+fn music_loops() {
+    let (mut manager, handle) = manager();
+    let mut music = manager
+        .create_music(
+            ramp(1000),
+            MusicParams {
+                loop_mix_time: 0.,
+                ..MusicParams::default()
+            },
+        )
+        .unwrap();
+
+    music.play().unwrap();
+    let frames = handle.render(1500);
+    assert!(frames[1000].0.abs() < 1e-4);
+    assert!((frames[1200].0 - 200. / SAMPLE_RATE as f32).abs() < 1e-4);
+}
+
+#[test]
+fn sfx_mixing() {
+    let (mut manager, handle) = manager();
+    let mut sfx = manager
+        .create_sfx(constant(Frame(0.25, -0.25), 1000), None)
+        .unwrap();
+
+    sfx.play(PlaySfxParams::default()).unwrap();
+    sfx.play(PlaySfxParams {
+        amplifier: 2.,
+        ..PlaySfxParams::default()
+    })
+    .unwrap();
+    let frames = handle.render(1500);
+    assert!(frames[..1000].iter().all(|it| *it == Frame(0.75, -0.75)));
+    // sfx positions accumulate in f32, so the frame right at the end may still be sampled
+    assert_silent(&frames[1001..]);
+}
+
+#[test]
+fn sfx_and_music_mix() {
+    let (mut manager, handle) = manager();
+    let mut music = manager
+        .create_music(constant(Frame(0.5, 0.5), 4800), MusicParams::default())
+        .unwrap();
+    let mut sfx = manager
+        .create_sfx(constant(Frame(0.25, 0.), 100), None)
+        .unwrap();
+
+    music.play().unwrap();
+    sfx.play(PlaySfxParams::default()).unwrap();
+    let frames = handle.render(200);
+    assert!(frames[..100].iter().all(|it| *it == Frame(0.75, 0.5)));
+    assert!(frames[101..].iter().all(|it| *it == Frame(0.5, 0.5)));
+}
+
+#[test]
+fn sfx_is_dropped_with_its_handle_after_playing() {
+    let (mut manager, handle) = manager();
+    let mut sfx = manager
+        .create_sfx(constant(Frame(0.25, 0.25), 100), None)
+        .unwrap();
+    sfx.play(PlaySfxParams::default()).unwrap();
+    drop(sfx);
+
+    // the queued play still finishes after the handle is gone
+    let frames = handle.render(200);
+    assert!(frames[..100].iter().all(|it| *it == Frame(0.25, 0.25)));
+    assert_silent(&frames[101..]);
+}
+
+#[test]
+fn mono_output() {
+    let (mut manager, handle) = manager();
+    let mut music = manager
+        .create_music(constant(Frame(0.5, 0.25), 4800), MusicParams::default())
+        .unwrap();
+    music.play().unwrap();
+    let frames = handle.render_with(
+        256,
+        &ManualSettings {
+            sample_rate: SAMPLE_RATE,
+            channels: 1,
+            block_size: 128,
+        },
+    );
+    assert!(frames.iter().all(|it| *it == Frame(0.375, 0.375)));
+}
+
+fn surround(block_size: usize) -> ManualSettings {
+    ManualSettings {
+        sample_rate: SAMPLE_RATE,
+        channels: 6,
+        block_size,
+    }
+}
+
+#[test]
+fn multichannel_front_upmix() {
+    let (mut manager, handle) = manager();
+    let mut music = manager
+        .create_music(constant(Frame(0.5, -0.5), 4800), MusicParams::default())
+        .unwrap();
+    let mut sfx = manager
+        .create_sfx(constant(Frame(0.25, 0.25), 4800), None)
+        .unwrap();
+    music.play().unwrap();
+    sfx.play(PlaySfxParams::default()).unwrap();
+
+    let data = handle.render_interleaved(256, &surround(128));
+    for frame in data.chunks_exact(6) {
+        assert_eq!(frame, [0.75, -0.25, 0., 0., 0., 0.]);
+    }
+}
+
+#[test]
+fn multichannel_surround_upmix() {
+    let (mut manager, handle) = manager();
+    manager.set_upmix(Upmix::Surround).unwrap();
+    let mut music = manager
+        .create_music(constant(Frame(0.5, 0.25), 4800), MusicParams::default())
+        .unwrap();
+    music.play().unwrap();
+
+    let half = std::f32::consts::FRAC_1_SQRT_2;
+    let data = handle.render_interleaved(256, &surround(128));
+    for frame in data.chunks_exact(6) {
+        let expected = [0.5, 0.25, 0.375 * half, 0., 0.5 * half, 0.25 * half];
+        for (a, b) in frame.iter().zip(expected) {
+            assert!((a - b).abs() < 1e-6);
+        }
+    }
+}
+
+#[test]
+fn native_multichannel_matches_upmix() {
+    // sfx render multichannel output themselves, music goes through the stereo upmix
+    let clip = ramp(4800);
+    let render = |sfx: bool| {
+        let (mut manager, handle) = manager();
+        manager.set_upmix(Upmix::Surround).unwrap();
+        if sfx {
+            let mut sfx = manager.create_sfx(clip.clone(), None).unwrap();
+            sfx.play(PlaySfxParams::default()).unwrap();
+            handle.render_interleaved(2048, &surround(512))
+        } else {
+            let mut music = manager
+                .create_music(clip.clone(), MusicParams::default())
+                .unwrap();
+            music.play().unwrap();
+            handle.render_interleaved(2048, &surround(512))
+        }
+    };
+    let native = render(true);
+    let upmixed = render(false);
+    assert!(native.iter().any(|it| *it != 0.));
+    for (a, b) in native.iter().zip(&upmixed) {
+        assert!((a - b).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn multichannel_sfx_advance_after_another_finishes() {
+    let (mut manager, handle) = manager();
+    let mut sfx = manager.create_sfx(ramp(100), None).unwrap();
+    let left = |data: Vec<f32>| data.chunks_exact(6).map(|it| it[0]).collect::<Vec<_>>();
+
+    sfx.play(PlaySfxParams::default()).unwrap();
+    handle.render_interleaved(60, &surround(60));
+    sfx.play(PlaySfxParams::default()).unwrap();
+    // the first play finishes during this callback
+    let data = left(handle.render_interleaved(60, &surround(60)));
+    assert!((data[0] - 60. / SAMPLE_RATE as f32).abs() < 1e-6);
+    assert!((data[50] - 50. / SAMPLE_RATE as f32).abs() < 1e-6);
+
+    // the second play goes on where it stopped
+    let data = left(handle.render_interleaved(60, &surround(60)));
+    for (i, sample) in data[..40].iter().enumerate() {
+        assert!((sample - (60 + i) as f32 / SAMPLE_RATE as f32).abs() < 1e-6);
+    }
+    assert!(data[41..].iter().all(|it| *it == 0.));
+}
+
+#[test]
+fn multichannel_callbacks_longer_than_the_upmix_buffer() {
+    let (mut manager, handle) = manager();
+    let mut music = manager
+        .create_music(ramp(48000), MusicParams::default())
+        .unwrap();
+    music.play().unwrap();
+
+    let data = handle.render_interleaved(20000, &surround(20000));
+    for (i, frame) in data.chunks_exact(6).enumerate() {
+        assert!((frame[0] - i as f32 / SAMPLE_RATE as f32).abs() < 1e-4);
+    }
+    assert!((music.position() - 20000. / SAMPLE_RATE as f32).abs() < 1e-6);
+}
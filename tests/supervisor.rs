@@ -0,0 +1,270 @@
+use anyhow::{bail, Result};
+use sasa::{
+    backend::{
+        manual::{ManualBackend, ManualHandle, ManualSettings},
+        supervisor::{
+            RecoveryReason, SupervisedBackend, SupervisorEvent, SupervisorEvents,
+            SupervisorSettings,
+        },
+        BackendSetup,
+    },
+    AudioClip, AudioManager, Backend, BackendState, Frame, MusicParams,
+};
+use std::{
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A manual backend whose `start` fails as long as `failures` is not zero.
+struct FlakyBackend {
+    inner: ManualBackend,
+    failures: Arc<AtomicU32>,
+    starts: Arc<AtomicU32>,
+}
+
+impl Backend for FlakyBackend {
+    fn setup(&mut self, setup: BackendSetup) -> Result<()> {
+        self.inner.setup(setup)
+    }
+
+    fn start(&mut self) -> Result<()> {
+        if self
+            .failures
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |it| it.checked_sub(1))
+            .is_ok()
+        {
+            bail!("device is unavailable");
+        }
+        self.inner.start()?;
+        self.starts.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        self.inner.stop()
+    }
+
+    fn suspend(&mut self) -> Result<()> {
+        self.inner.suspend()
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        self.inner.resume()
+    }
+
+    fn state(&self) -> BackendState {
+        self.inner.state()
+    }
+
+    fn consume_broken(&self) -> bool {
+        self.inner.consume_broken()
+    }
+}
+
+struct Setup {
+    manager: AudioManager,
+    events: SupervisorEvents,
+    handle: ManualHandle,
+    failures: Arc<AtomicU32>,
+    starts: Arc<AtomicU32>,
+}
+
+fn setup(settings: SupervisorSettings) -> Setup {
+    let inner = ManualBackend::new(ManualSettings::default());
+    let handle = inner.handle();
+    let failures = Arc::<AtomicU32>::default();
+    let starts = Arc::<AtomicU32>::default();
+    let backend = FlakyBackend {
+        inner,
+        failures: Arc::clone(&failures),
+        starts: Arc::clone(&starts),
+    };
+    let (backend, events) = SupervisedBackend::new(move || backend, settings);
+    Setup {
+        manager: AudioManager::new(backend).unwrap(),
+        events,
+        handle,
+        failures,
+        starts,
+    }
+}
+
+fn fast() -> SupervisorSettings {
+    SupervisorSettings {
+        poll_interval: Duration::from_millis(10),
+        ..SupervisorSettings::default()
+    }
+}
+
+fn next_event(events: &mut SupervisorEvents) -> SupervisorEvent {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        if let Some(event) = events.next() {
+            return event;
+        }
+        assert!(Instant::now() < deadline, "no supervisor event");
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn recovers_lost_device() {
+    let Setup {
+        mut manager,
+        mut events,
+        handle,
+        starts,
+        ..
+    } = setup(fast());
+    let mut music = manager
+        .create_music(
+            AudioClip::from_raw(vec![Frame(0.5, 0.5); 48000], 48000),
+            MusicParams::default(),
+        )
+        .unwrap();
+    music.play().unwrap();
+    handle.render(512);
+
+    handle.mark_broken();
+    assert!(matches!(
+        next_event(&mut events),
+        SupervisorEvent::Recovered(RecoveryReason::DeviceLost)
+    ));
+    assert_eq!(starts.load(Ordering::Relaxed), 2);
+    assert_eq!(manager.state(), BackendState::Running);
+    assert!(!manager.consume_broken());
+
+    // the renderers survive the restart
+    assert!(handle.render(512).iter().all(|it| *it == Frame(0.5, 0.5)));
+    assert!((music.position() - 1024. / 48000.).abs() < 1e-6);
+}
+
+#[test]
+fn retries_failed_recovery() {
+    let Setup {
+        mut manager,
+        mut events,
+        handle,
+        failures,
+        starts,
+    } = setup(fast());
+
+    failures.store(2, Ordering::Relaxed);
+    handle.mark_broken();
+    for _ in 0..2 {
+        assert!(matches!(
+            next_event(&mut events),
+            SupervisorEvent::RecoveryFailed(RecoveryReason::DeviceLost, _)
+        ));
+    }
+    assert!(matches!(
+        next_event(&mut events),
+        SupervisorEvent::Recovered(RecoveryReason::DeviceLost)
+    ));
+    assert_eq!(starts.load(Ordering::Relaxed), 2);
+    // failures are still reported to the application
+    assert!(manager.consume_broken());
+    assert!(!manager.consume_broken());
+    assert_eq!(manager.state(), BackendState::Running);
+
+    manager.stop().unwrap();
+}
+
+#[test]
+fn restores_suspended_state() {
+    let Setup {
+        mut manager,
+        mut events,
+        handle,
+        ..
+    } = setup(fast());
+
+    manager.suspend().unwrap();
+    handle.mark_broken();
+    assert!(matches!(
+        next_event(&mut events),
+        SupervisorEvent::Recovered(RecoveryReason::DeviceLost)
+    ));
+    assert_eq!(manager.state(), BackendState::Suspended);
+}
+
+#[test]
+fn stopped_stream_is_not_restarted() {
+    let Setup {
+        mut manager,
+        mut events,
+        handle,
+        starts,
+        ..
+    } = setup(fast());
+
+    manager.stop().unwrap();
+    handle.mark_broken();
+    thread::sleep(Duration::from_millis(100));
+    assert!(events.next().is_none());
+    assert_eq!(starts.load(Ordering::Relaxed), 1);
+    assert_eq!(manager.state(), BackendState::Stopped);
+}
+
+#[test]
+fn commands_do_not_postpone_checks() {
+    let Setup {
+        mut manager,
+        mut events,
+        handle,
+        ..
+    } = setup(SupervisorSettings {
+        poll_interval: Duration::from_millis(50),
+        ..SupervisorSettings::default()
+    });
+
+    handle.mark_broken();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let event = loop {
+        // each command arrives well within the poll interval
+        manager.resume().unwrap();
+        if let Some(event) = events.next() {
+            break event;
+        }
+        assert!(Instant::now() < deadline, "check was postponed");
+        thread::sleep(Duration::from_millis(5));
+    };
+    assert!(matches!(
+        event,
+        SupervisorEvent::Recovered(RecoveryReason::DeviceLost)
+    ));
+}
+
+#[test]
+fn counts_dropped_events() {
+    let Setup {
+        manager: _manager,
+        events,
+        handle,
+        starts,
+        ..
+    } = setup(SupervisorSettings {
+        event_buffer_size: 1,
+        ..fast()
+    });
+
+    for i in 0..3 {
+        handle.mark_broken();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while starts.load(Ordering::Relaxed) != i + 2 {
+            assert!(Instant::now() < deadline, "no recovery");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+    // the event is pushed right after the restart
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while events.dropped() != 2 {
+        assert!(Instant::now() < deadline, "events were not counted");
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(events.count(), 1);
+}